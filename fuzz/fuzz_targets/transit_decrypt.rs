@@ -0,0 +1,36 @@
+#![no_main]
+
+use base64::engine::general_purpose;
+use base64::Engine;
+use libfuzzer_sys::fuzz_target;
+use vaultapi::decipher::{transit_decrypt, transit_encrypt_bytes, HmacPlacement, TransitOptions};
+
+// The first byte selects the transit settings and how the rest of the input reaches `transit_decrypt`,
+// which must return an `Err` for anything it can't decrypt, and never panic.
+fuzz_target!(|data: &[u8]| {
+    let Some((&flags, input)) = data.split_first() else {
+        return;
+    };
+    let options = TransitOptions {
+        // A fixed epoch, so the sealed inputs are reproducible
+        epoch_override: Some(1_700_000_000),
+        constant_time: flags & 1 != 0,
+        in_place: flags & 2 != 0,
+        verify_hmac: flags & 4 != 0,
+        hmac_placement: if flags & 8 != 0 { HmacPlacement::Suffix } else { HmacPlacement::Prefix },
+        ..TransitOptions::default()
+    };
+    let apikey = "fuzz".to_string();
+    let ciphertext = match flags >> 4 {
+        // Arbitrary text, for the base64 decoding
+        0..=5 => String::from_utf8_lossy(input).to_string(),
+        // Arbitrary decoded bytes, for the length checks, the HMAC and the authentication
+        6..=10 => general_purpose::STANDARD.encode(input),
+        // Arbitrary plaintext sealed with the matching key, for the JSON parsing, eg: invalid UTF-8
+        _ => match transit_encrypt_bytes(&apikey, input, &options, None) {
+            Ok(ciphertext) => ciphertext,
+            Err(_) => return,
+        },
+    };
+    let _ = transit_decrypt(&apikey, &ciphertext, &options, None);
+});
@@ -0,0 +1,81 @@
+use ring::rand::{SecureRandom, SystemRandom};
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+/// Number of random names tried for the temporary file, before giving up on a directory full of collisions.
+const TEMP_ATTEMPTS: usize = 8;
+
+/// Creates a temporary file next to the path, under a random name that no other file or symlink uses.
+///
+/// The file is opened with `create_new`, so a file planted at the temporary path is never written through.
+///
+/// # Arguments
+/// * `path` - Destination file path the temporary file will be renamed to.
+/// * `mode` - Unix permissions of the file regardless of the umask, ignored on other platforms.
+///
+/// # Returns
+/// * A `Result` containing the open file and its path, or the IO error.
+fn create_temp(path: &Path, mode: Option<u32>) -> std::io::Result<(fs::File, PathBuf)> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    for _ in 0..TEMP_ATTEMPTS {
+        let mut suffix = [0u8; 8];
+        if SystemRandom::new().fill(&mut suffix).is_err() {
+            return Err(Error::other("Failed to generate a temporary file name"));
+        }
+        let suffix: String = suffix.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let temp_path = path.with_file_name(format!(".{}.{}.tmp", name, suffix));
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        if let Some(mode) = mode {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(mode);
+        }
+        match options.open(&temp_path) {
+            Ok(file) => {
+                // The mode is masked by the umask on creation, the file is ours so it can be set as is
+                #[cfg(unix)]
+                if let Some(mode) = mode {
+                    use std::os::unix::fs::PermissionsExt;
+                    if let Err(err) = file.set_permissions(fs::Permissions::from_mode(mode)) {
+                        let _ = fs::remove_file(&temp_path);
+                        return Err(err);
+                    }
+                }
+                #[cfg(not(unix))]
+                let _ = mode;
+                return Ok((file, temp_path));
+            }
+            // Left by a concurrent writer or planted, either way not ours to touch
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Err(Error::new(ErrorKind::AlreadyExists, "Failed to find an unused temporary file name"))
+}
+
+/// Writes a file atomically, via a temporary file that is synced and renamed over the path once complete.
+///
+/// The temporary file is removed on failure, so an interrupted write never leaves a partial file behind.
+///
+/// # Arguments
+/// * `path` - Destination file path.
+/// * `mode` - Unix permissions of the file regardless of the umask, ignored on other platforms.
+/// * `write` - Writes the content to the temporary file, returning a value passed through on success.
+///
+/// # Returns
+/// * A `Result` containing the value returned by `write`, or the IO error.
+pub(crate) fn write_atomic<T, F>(path: &Path, mode: Option<u32>, write: F) -> std::io::Result<T>
+where
+    F: FnOnce(&mut fs::File) -> std::io::Result<T>,
+{
+    let (mut file, temp_path) = create_temp(path, mode)?;
+    let result = write(&mut file)
+        .and_then(|value| file.sync_all().map(|_| value))
+        .and_then(|value| fs::rename(&temp_path, path).map(|_| value));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
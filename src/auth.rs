@@ -0,0 +1,37 @@
+use reqwest::{Method, RequestBuilder, Url};
+
+/// Details of the request being authenticated.
+pub struct AuthContext<'a> {
+    /// HTTP method of the request.
+    pub method: &'a Method,
+    /// Request URL, including the query parameters.
+    pub url: &'a Url,
+    /// APIkey configured for the table the request is for, or the global apikey.
+    pub apikey: &'a str,
+}
+
+/// Scheme used to authenticate the requests made by a `VaultClient`, eg: to sign each request with an HMAC.
+///
+/// The scheme is applied to every attempt of a request, right before it is sent, so time based signatures
+/// are computed afresh for each retry.
+pub trait Auth: Send + Sync {
+    /// Authenticates a request, eg: by adding headers.
+    ///
+    /// # Arguments
+    /// * `request` - Request with the query parameters, the body and the other headers already set.
+    /// * `context` - Method, URL and apikey of the request.
+    ///
+    /// # Returns
+    /// * The authenticated `RequestBuilder`.
+    fn authenticate(&self, request: RequestBuilder, context: &AuthContext) -> RequestBuilder;
+}
+
+/// Sends the apikey as an `Authorization: Bearer <apikey>` header, used by default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BearerAuth;
+
+impl Auth for BearerAuth {
+    fn authenticate(&self, request: RequestBuilder, context: &AuthContext) -> RequestBuilder {
+        request.bearer_auth(context.apikey)
+    }
+}
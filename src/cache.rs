@@ -0,0 +1,232 @@
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+/// Decrypted secret held by the cache.
+struct CachedSecret {
+    stored: Instant,
+    ttl: Duration,
+    value: Value,
+    // Generation of the last use, the key of the secret in `order`
+    used: u64,
+}
+
+/// In-memory LRU cache of decrypted secrets, keyed by table name and secret key.
+pub(crate) struct SecretCache {
+    ttl: Duration,
+    max_entries: usize,
+    entries: HashMap<(String, String), CachedSecret>,
+    // Keys by the generation of their last use, least recently used first
+    order: BTreeMap<u64, (String, String)>,
+    generation: u64,
+}
+
+impl SecretCache {
+    /// Creates an empty cache.
+    ///
+    /// # Arguments
+    /// * `ttl` - Duration for which a secret is served from the cache, `0` disables the cache.
+    /// * `max_entries` - Maximum number of secrets to keep, the least recently used are evicted first.
+    ///
+    /// # Returns
+    /// * A `SecretCache` object.
+    pub(crate) fn new(ttl: Duration, max_entries: usize) -> Self {
+        SecretCache {
+            ttl,
+            max_entries,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            generation: 0,
+        }
+    }
+
+    /// Checks if the cache is in use.
+    pub(crate) fn enabled(&self) -> bool {
+        !self.ttl.is_zero() && self.max_entries > 0
+    }
+
+    /// Retrieves a secret that hasn't outlived the TTL.
+    ///
+    /// # Arguments
+    /// * `table_name` - Name of the table the secret belongs to.
+    /// * `key` - Name of the secret.
+    ///
+    /// # Returns
+    /// * An `Option<Value>` containing the decrypted secret, `None` if missing or expired.
+    pub(crate) fn get(&mut self, table_name: &str, key: &str) -> Option<Value> {
+        let cache_key = (table_name.to_string(), key.to_string());
+        let entry = self.entries.get_mut(&cache_key)?;
+        self.order.remove(&entry.used);
+        if entry.stored.elapsed() >= entry.ttl {
+            self.entries.remove(&cache_key);
+            return None;
+        }
+        // Moves the key to the most recently used end
+        self.generation += 1;
+        entry.used = self.generation;
+        let value = entry.value.clone();
+        self.order.insert(self.generation, cache_key);
+        Some(value)
+    }
+
+    /// Stores a secret, evicting the least recently used ones beyond `max_entries`.
+    ///
+    /// # Arguments
+    /// * `table_name` - Name of the table the secret belongs to.
+    /// * `key` - Name of the secret.
+    /// * `value` - Decrypted secret.
+    /// * `ttl` - Duration to serve this secret for instead of the cache's TTL, eg: from the server. `0` skips the cache.
+    pub(crate) fn insert(&mut self, table_name: &str, key: &str, value: Value, ttl: Option<Duration>) {
+        let ttl = ttl.unwrap_or(self.ttl);
+        if ttl.is_zero() {
+            self.remove(table_name, key);
+            return;
+        }
+        self.generation += 1;
+        let cache_key = (table_name.to_string(), key.to_string());
+        let entry = CachedSecret { stored: Instant::now(), ttl, value, used: self.generation };
+        if let Some(previous) = self.entries.insert(cache_key.clone(), entry) {
+            self.order.remove(&previous.used);
+        }
+        self.order.insert(self.generation, cache_key);
+        while self.entries.len() > self.max_entries {
+            match self.order.pop_first() {
+                Some((_, evicted)) => self.entries.remove(&evicted),
+                None => break,
+            };
+        }
+    }
+
+    /// Removes a single secret.
+    ///
+    /// # Arguments
+    /// * `table_name` - Name of the table the secret belongs to.
+    /// * `key` - Name of the secret.
+    pub(crate) fn remove(&mut self, table_name: &str, key: &str) {
+        let cache_key = (table_name.to_string(), key.to_string());
+        if let Some(entry) = self.entries.remove(&cache_key) {
+            self.order.remove(&entry.used);
+        }
+    }
+
+    /// Removes all the secrets.
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Checks that every cached secret is tracked once in the LRU order.
+    fn assert_consistent(cache: &SecretCache) {
+        assert_eq!(cache.entries.len(), cache.order.len());
+        for (used, key) in &cache.order {
+            assert_eq!(cache.entries[key].used, *used);
+        }
+    }
+
+    #[test]
+    fn least_recently_used_secret_is_evicted_first() {
+        let mut cache = SecretCache::new(Duration::from_secs(60), 2);
+        cache.insert("default", "a", json!(1), None);
+        cache.insert("default", "b", json!(2), None);
+        // Reading `a` makes `b` the least recently used
+        assert_eq!(cache.get("default", "a"), Some(json!(1)));
+        cache.insert("default", "c", json!(3), None);
+        assert_eq!(cache.get("default", "b"), None);
+        assert_eq!(cache.get("default", "a"), Some(json!(1)));
+        assert_eq!(cache.get("default", "c"), Some(json!(3)));
+        cache.insert("default", "d", json!(4), None);
+        assert_eq!(cache.get("default", "a"), None);
+        assert_consistent(&cache);
+    }
+
+    #[test]
+    fn replacing_a_secret_keeps_a_single_entry() {
+        let mut cache = SecretCache::new(Duration::from_secs(60), 2);
+        cache.insert("default", "a", json!(1), None);
+        cache.insert("default", "b", json!(2), None);
+        cache.insert("default", "a", json!(3), None);
+        assert_eq!(cache.entries.len(), 2);
+        assert_eq!(cache.get("default", "a"), Some(json!(3)));
+        assert_eq!(cache.get("default", "b"), Some(json!(2)));
+        assert_consistent(&cache);
+    }
+
+    #[test]
+    fn secrets_are_keyed_by_table_and_key() {
+        let mut cache = SecretCache::new(Duration::from_secs(60), 8);
+        cache.insert("default", "a", json!(1), None);
+        cache.insert("other", "a", json!(2), None);
+        assert_eq!(cache.get("default", "a"), Some(json!(1)));
+        assert_eq!(cache.get("other", "a"), Some(json!(2)));
+        assert_eq!(cache.get("missing", "a"), None);
+    }
+
+    #[test]
+    fn secret_expires_after_its_ttl() {
+        let mut cache = SecretCache::new(Duration::from_secs(60), 8);
+        cache.insert("default", "short", json!(1), Some(Duration::from_millis(20)));
+        cache.insert("default", "long", json!(2), None);
+        assert_eq!(cache.get("default", "short"), Some(json!(1)));
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(cache.get("default", "short"), None);
+        assert_eq!(cache.get("default", "long"), Some(json!(2)));
+        // The expired secret is dropped, not only hidden
+        assert!(!cache.entries.contains_key(&("default".to_string(), "short".to_string())));
+        assert_consistent(&cache);
+    }
+
+    #[test]
+    fn zero_ttl_removes_the_cached_secret() {
+        let mut cache = SecretCache::new(Duration::from_secs(60), 8);
+        cache.insert("default", "a", json!(1), None);
+        cache.insert("default", "a", json!(2), Some(Duration::ZERO));
+        assert_eq!(cache.get("default", "a"), None);
+        assert!(cache.entries.is_empty());
+        assert_consistent(&cache);
+    }
+
+    #[test]
+    fn cache_is_disabled_without_a_ttl_or_entries() {
+        assert!(SecretCache::new(Duration::from_secs(60), 1).enabled());
+        assert!(!SecretCache::new(Duration::ZERO, 256).enabled());
+        assert!(!SecretCache::new(Duration::from_secs(60), 0).enabled());
+        let mut cache = SecretCache::new(Duration::from_secs(60), 0);
+        cache.insert("default", "a", json!(1), None);
+        assert!(cache.entries.is_empty());
+        assert_consistent(&cache);
+    }
+
+    #[test]
+    fn max_entries_bounds_the_cache() {
+        let mut cache = SecretCache::new(Duration::from_secs(60), 3);
+        for index in 0..10 {
+            cache.insert("default", &index.to_string(), json!(index), None);
+        }
+        assert_eq!(cache.entries.len(), 3);
+        for index in 7..10 {
+            assert_eq!(cache.get("default", &index.to_string()), Some(json!(index)));
+        }
+        assert_consistent(&cache);
+    }
+
+    #[test]
+    fn removed_and_cleared_secrets_are_no_longer_served() {
+        let mut cache = SecretCache::new(Duration::from_secs(60), 8);
+        cache.insert("default", "a", json!(1), None);
+        cache.insert("default", "b", json!(2), None);
+        cache.remove("default", "a");
+        cache.remove("default", "missing");
+        assert_eq!(cache.get("default", "a"), None);
+        assert_consistent(&cache);
+        cache.clear();
+        assert_eq!(cache.get("default", "b"), None);
+        assert_consistent(&cache);
+    }
+}
@@ -0,0 +1,272 @@
+use crate::auth::{Auth, BearerAuth};
+use crate::cache::SecretCache;
+use crate::error::Error;
+use crate::config::Config;
+use crate::discovery;
+use crate::metrics::{Metrics, NoopMetrics};
+use serde_json::Value;
+use std::future::Future;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::Duration;
+
+/// Client to interact with the VaultAPI server, reusing a single connection pool across requests.
+///
+/// The runtime driving the blocking methods is only created on their first call, so a client used through the
+/// `*_async` methods alone can be created and dropped within the async runtime of the application.
+pub struct VaultClient {
+    pub config: Config,
+    pub(crate) http: reqwest::Client,
+    runtime: OnceLock<tokio::runtime::Runtime>,
+    cache: Mutex<SecretCache>,
+    pub(crate) metrics: Arc<dyn Metrics>,
+    pub(crate) auth: Arc<dyn Auth>,
+}
+
+impl VaultClient {
+    /// Creates a client with the default connection pool settings.
+    ///
+    /// # Arguments
+    /// * `config` - Config object to retrieve environment variables, and command line arguments.
+    ///
+    /// # Returns
+    /// * A `Result<VaultClient, Error>` containing the client.
+    pub fn new(config: Config) -> Result<Self, Error> {
+        VaultClientBuilder::new(config).build()
+    }
+
+    /// Creates a builder to customize the connection pool.
+    ///
+    /// # Arguments
+    /// * `config` - Config object to retrieve environment variables, and command line arguments.
+    ///
+    /// # Returns
+    /// * A `VaultClientBuilder` with the default connection pool settings.
+    pub fn builder(config: Config) -> VaultClientBuilder {
+        VaultClientBuilder::new(config)
+    }
+
+    /// Drives a future to completion on the client's runtime, creating the runtime on the first call.
+    ///
+    /// # Arguments
+    /// * `future` - Future to wait on.
+    ///
+    /// # Returns
+    /// * The output of the future, or an error if the runtime can't be created.
+    pub(crate) fn block_on<T, F: Future<Output = Result<T, Error>>>(&self, future: F) -> Result<T, Error> {
+        if let Some(runtime) = self.runtime.get() {
+            return runtime.block_on(future);
+        }
+        let runtime = new_runtime()?;
+        // Another thread may have created its runtime in the meantime, either one can be used
+        let runtime = self.runtime.get_or_init(|| runtime);
+        runtime.block_on(future)
+    }
+
+    /// Locks the secret cache, which a panic while holding the lock can't leave inconsistent.
+    fn cache(&self) -> MutexGuard<'_, SecretCache> {
+        self.cache.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Retrieves a secret from the cache, if enabled with `cache_ttl` and not expired.
+    pub(crate) fn cached_secret(&self, table_name: &str, key: &str) -> Option<Value> {
+        let mut cache = self.cache();
+        if !cache.enabled() {
+            return None;
+        }
+        cache.get(table_name, key)
+    }
+
+    /// Stores a secret in the cache, if enabled with `cache_ttl`, for the server's `max-age` when given.
+    pub(crate) fn cache_secret(&self, table_name: &str, key: &str, value: &Value, max_age: Option<Duration>) {
+        let mut cache = self.cache();
+        if cache.enabled() {
+            cache.insert(table_name, key, value.clone(), max_age);
+        }
+    }
+
+    /// Removes a secret from the cache, after it is changed on the server.
+    pub(crate) fn evict_secret(&self, table_name: &str, key: &str) {
+        self.cache().remove(table_name, key);
+    }
+
+    /// Removes all the secrets from the cache, so the next retrievals go to the server.
+    pub fn clear_cache(&self) {
+        self.cache().clear();
+    }
+}
+
+/// Loads a PEM encoded CA certificate.
+///
+/// # Arguments
+/// * `path` - Path to the PEM file.
+///
+/// # Returns
+/// * A `Result<reqwest::Certificate, Error>` containing the certificate.
+fn load_certificate(path: &str) -> Result<reqwest::Certificate, Error> {
+    let pem = match std::fs::read(path) {
+        Ok(pem) => pem,
+        Err(err) => return Err(Error::Config(format!("Failed to read CA certificate {}: {}", path, err))),
+    };
+    match reqwest::Certificate::from_pem(&pem) {
+        Ok(certificate) => Ok(certificate),
+        Err(err) => Err(Error::Config(format!("Failed to parse CA certificate {}: {}", path, err))),
+    }
+}
+
+/// Creates the single-threaded runtime driving the blocking methods of the client.
+///
+/// # Returns
+/// * A `Result<tokio::runtime::Runtime, Error>` containing the runtime.
+fn new_runtime() -> Result<tokio::runtime::Runtime, Error> {
+    match tokio::runtime::Builder::new_current_thread().enable_all().build() {
+        Ok(runtime) => Ok(runtime),
+        Err(err) => Err(Error::Request(format!("Failed to build the async runtime: {}", err))),
+    }
+}
+
+/// Builder for `VaultClient` to set the connection pool size, idle timeout and the cache size.
+pub struct VaultClientBuilder {
+    config: Config,
+    pool_max_idle_per_host: usize,
+    pool_idle_timeout: Option<Duration>,
+    cache_max_entries: usize,
+    metrics: Arc<dyn Metrics>,
+    auth: Arc<dyn Auth>,
+}
+
+impl VaultClientBuilder {
+    /// Creates a builder with the default connection pool settings, and the idle timeout of the config.
+    ///
+    /// # Arguments
+    /// * `config` - Config object to retrieve environment variables, and command line arguments.
+    ///
+    /// # Returns
+    /// * A `VaultClientBuilder` object.
+    pub fn new(config: Config) -> Self {
+        VaultClientBuilder {
+            pool_idle_timeout: config.pool_idle_timeout,
+            config,
+            pool_max_idle_per_host: usize::MAX,
+            cache_max_entries: 256,
+            metrics: Arc::new(NoopMetrics),
+            auth: Arc::new(BearerAuth),
+        }
+    }
+
+    /// Sets the maximum number of idle connections kept per host.
+    pub fn pool_max_idle_per_host(mut self, max: usize) -> Self {
+        self.pool_max_idle_per_host = max;
+        self
+    }
+
+    /// Sets how long idle connections are kept alive, `None` to keep them indefinitely.
+    pub fn pool_idle_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.pool_idle_timeout = timeout;
+        self
+    }
+
+    /// Sets the maximum number of secrets kept in the cache, when enabled with `cache_ttl`.
+    pub fn cache_max_entries(mut self, max: usize) -> Self {
+        self.cache_max_entries = max;
+        self
+    }
+
+    /// Sets the hook notified of the requests and decryptions, see `Metrics`.
+    pub fn metrics(mut self, metrics: Arc<dyn Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Sets the scheme used to authenticate the requests, `BearerAuth` by default, see `Auth`.
+    pub fn auth(mut self, auth: Arc<dyn Auth>) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    /// Builds the client, from outside an async runtime.
+    ///
+    /// With `discover` set, the discovery document is fetched here to resolve the endpoint paths, on the runtime
+    /// then kept for the blocking methods. Use `build_async` from within an async runtime instead.
+    ///
+    /// # Returns
+    /// * A `Result<VaultClient, Error>` containing the client.
+    pub fn build(mut self) -> Result<VaultClient, Error> {
+        let http = self.http_client()?;
+        if self.config.discover.is_empty() {
+            return Ok(self.finish(http, OnceLock::new()));
+        }
+        let runtime = new_runtime()?;
+        let discovery = runtime.block_on(discovery::discover(&http, &self.config.discover))?;
+        discovery.apply(&mut self.config);
+        Ok(self.finish(http, OnceLock::from(runtime)))
+    }
+
+    /// Builds the client from within an async runtime, fetching the discovery document when `discover` is set.
+    ///
+    /// # Returns
+    /// * A `Result<VaultClient, Error>` containing the client.
+    pub async fn build_async(mut self) -> Result<VaultClient, Error> {
+        let http = self.http_client()?;
+        if !self.config.discover.is_empty() {
+            let discovery = discovery::discover(&http, &self.config.discover).await?;
+            discovery.apply(&mut self.config);
+        }
+        Ok(self.finish(http, OnceLock::new()))
+    }
+
+    /// Builds the HTTP client with the connection pool, TLS and proxy settings.
+    ///
+    /// # Returns
+    /// * A `Result<reqwest::Client, Error>` containing the HTTP client.
+    fn http_client(&self) -> Result<reqwest::Client, Error> {
+        let mut builder = reqwest::Client::builder()
+            .timeout(self.config.timeout)
+            .pool_max_idle_per_host(self.pool_max_idle_per_host)
+            .pool_idle_timeout(self.pool_idle_timeout)
+            .tcp_keepalive(self.config.tcp_keepalive);
+        if self.config.http2_prior_knowledge {
+            builder = builder.http2_prior_knowledge();
+        }
+        if !self.config.ca_cert.is_empty() {
+            builder = builder.add_root_certificate(load_certificate(&self.config.ca_cert)?);
+        }
+        if !self.config.proxy.is_empty() {
+            let no_proxy = if self.config.no_env { None } else { reqwest::NoProxy::from_env() };
+            let proxy = match reqwest::Proxy::all(&self.config.proxy) {
+                Ok(proxy) => proxy.no_proxy(no_proxy),
+                Err(err) => return Err(Error::Config(format!("Invalid proxy URL: {}", err))),
+            };
+            builder = builder.proxy(proxy);
+        } else if self.config.no_env {
+            // reqwest would otherwise pick up HTTP_PROXY, HTTPS_PROXY and ALL_PROXY from the environment on its own
+            builder = builder.no_proxy();
+        }
+        if !self.config.user_agent.is_empty() {
+            builder = builder.user_agent(&self.config.user_agent);
+        }
+        if !self.config.compression {
+            builder = builder.no_gzip().no_deflate();
+        }
+        if self.config.insecure {
+            log::warn!("TLS certificate verification is DISABLED, do not use --insecure in production!");
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+        match builder.build() {
+            Ok(client) => Ok(client),
+            Err(err) => Err(Error::Request(format!("Failed to build the HTTP client: {}", err))),
+        }
+    }
+
+    /// Creates the client from the HTTP client, and the runtime when already created for the discovery.
+    fn finish(self, http: reqwest::Client, runtime: OnceLock<tokio::runtime::Runtime>) -> VaultClient {
+        let cache = Mutex::new(SecretCache::new(self.config.cache_ttl, self.cache_max_entries));
+        VaultClient {
+            config: self.config,
+            http,
+            runtime,
+            cache,
+            metrics: self.metrics,
+            auth: self.auth,
+        }
+    }
+}
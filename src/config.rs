@@ -0,0 +1,408 @@
+use reqwest::Url;
+use crate::decipher::{Base64Variant, CipherSuite, DerivationOrder, HashAlgorithm, HmacPlacement, PayloadCompression, TransitOptions};
+use crate::error::ConfigError;
+use std::collections::HashMap;
+use std::time::Duration;
+
+pub(crate) const TRANSIT_KEY_LENGTH: usize = 32;
+pub(crate) const TRANSIT_TIME_BUCKET: u64 = 60;
+pub(crate) const TRANSIT_TOLERANCE: u64 = 1;
+pub(crate) const TRANSIT_TAG_LENGTH: usize = 16;
+pub(crate) const TRANSIT_HMAC_LENGTH: usize = 32;
+pub(crate) const TRANSIT_DERIVATION_SEPARATOR: &str = ".";
+pub(crate) const TIMEOUT: u64 = 30;
+pub(crate) const POOL_IDLE_TIMEOUT: u64 = 90;
+pub(crate) const RETRIES: u32 = 3;
+pub(crate) const RETRY_BACKOFF_MS: u64 = 500;
+pub(crate) const REQUEST_JITTER_MS: u64 = 0;
+pub(crate) const PAGE_SIZE: u32 = 0;
+pub(crate) const PARALLEL_LIMIT: usize = 8;
+pub(crate) const ACCEPT: &str = "application/json";
+pub(crate) const PATH_GET_SECRET: &str = "get-secret";
+pub(crate) const PATH_GET_SECRETS: &str = "get-secrets";
+pub(crate) const PATH_GET_TABLE: &str = "get-table";
+pub(crate) const PATH_PUT_SECRET: &str = "put-secret";
+pub(crate) const PATH_DELETE_SECRET: &str = "delete-secret";
+pub(crate) const PATH_LIST_TABLES: &str = "list-tables";
+pub(crate) const PATH_LIST_KEYS: &str = "list-keys";
+pub(crate) const PATH_WATCH: &str = "watch";
+
+/// Settings for the client, resolved from the arguments, env vars and the config file by `parser::arguments`.
+///
+/// Fields may be added in any release, so the config is created with `Config::new` or `Config::builder`.
+#[non_exhaustive]
+pub struct Config {
+    pub vault_server: Url,
+    pub fallback_server: Option<Url>,
+    pub apikey: String,
+    pub apikey_previous: String,
+    pub table_apikeys: HashMap<String, String>,
+    pub transit_key_length: usize,
+    pub transit_time_bucket: u64,
+    pub transit_tolerance: u64,
+    pub transit_tag_length: usize,
+    pub transit_epoch_override: Option<u64>,
+    pub cipher_suite: CipherSuite,
+    pub hash_algorithm: HashAlgorithm,
+    pub base64_variant: Base64Variant,
+    pub transit_derivation_order: DerivationOrder,
+    pub transit_derivation_separator: String,
+    pub transit_constant_time: bool,
+    pub transit_in_place: bool,
+    pub transit_verify_hmac: bool,
+    pub transit_hmac_length: usize,
+    pub transit_hmac_placement: HmacPlacement,
+    pub transit_decompress: PayloadCompression,
+    pub transit_aad: bool,
+    pub timeout: Duration,
+    pub http2_prior_knowledge: bool,
+    pub tcp_keepalive: Option<Duration>,
+    pub pool_idle_timeout: Option<Duration>,
+    pub retries: u32,
+    pub retry_backoff_ms: u64,
+    pub request_jitter_ms: u64,
+    pub ca_cert: String,
+    pub insecure: bool,
+    pub proxy: String,
+    pub no_env: bool,
+    pub namespace: String,
+    pub path_get_secret: String,
+    pub path_get_secrets: String,
+    pub path_get_table: String,
+    pub path_put_secret: String,
+    pub path_delete_secret: String,
+    pub path_list_tables: String,
+    pub path_list_keys: String,
+    pub path_watch: String,
+    pub discover: String,
+    pub extra_headers: HashMap<String, String>,
+    pub accept: String,
+    pub compression: bool,
+    pub user_agent: String,
+    pub request_id: String,
+    pub page_size: u32,
+    pub repeat_keys: bool,
+    pub percent_encode: bool,
+    pub cache_ttl: Duration,
+
+    pub cipher: String,
+    pub table_name: String,
+    pub get_secret: String,
+    pub get_secrets: String,
+    pub get_table: String,
+    pub put_secret: String,
+    pub put_value: String,
+    pub delete_secret: String,
+    pub list_tables: bool,
+    pub list_keys: bool,
+    pub parallel: bool,
+    pub parallel_limit: usize,
+    pub require_all: bool,
+}
+
+impl Config {
+    /// Creates a config with the default settings, for library use without parsing the command line.
+    ///
+    /// # Arguments
+    /// * `vault_server` - Base URL of the VaultAPI server, see `parse_vault_server`.
+    /// * `apikey` - APIkey to authenticate the server, and to derive the transit key.
+    ///
+    /// # Returns
+    /// * A `Config` object.
+    pub fn new(vault_server: Url, apikey: String) -> Self {
+        Config {
+            vault_server,
+            fallback_server: None,
+            apikey,
+            apikey_previous: String::new(),
+            table_apikeys: HashMap::new(),
+            transit_key_length: TRANSIT_KEY_LENGTH,
+            transit_time_bucket: TRANSIT_TIME_BUCKET,
+            transit_tolerance: TRANSIT_TOLERANCE,
+            transit_tag_length: TRANSIT_TAG_LENGTH,
+            transit_epoch_override: None,
+            cipher_suite: CipherSuite::default(),
+            hash_algorithm: HashAlgorithm::default(),
+            base64_variant: Base64Variant::default(),
+            transit_derivation_order: DerivationOrder::default(),
+            transit_derivation_separator: TRANSIT_DERIVATION_SEPARATOR.to_string(),
+            transit_constant_time: false,
+            transit_in_place: false,
+            transit_verify_hmac: false,
+            transit_hmac_length: TRANSIT_HMAC_LENGTH,
+            transit_hmac_placement: HmacPlacement::default(),
+            transit_decompress: PayloadCompression::default(),
+            transit_aad: false,
+            timeout: Duration::from_secs(TIMEOUT),
+            http2_prior_knowledge: false,
+            tcp_keepalive: None,
+            pool_idle_timeout: Some(Duration::from_secs(POOL_IDLE_TIMEOUT)),
+            retries: RETRIES,
+            retry_backoff_ms: RETRY_BACKOFF_MS,
+            request_jitter_ms: REQUEST_JITTER_MS,
+            ca_cert: String::new(),
+            insecure: false,
+            proxy: String::new(),
+            no_env: false,
+            namespace: String::new(),
+            path_get_secret: PATH_GET_SECRET.to_string(),
+            path_get_secrets: PATH_GET_SECRETS.to_string(),
+            path_get_table: PATH_GET_TABLE.to_string(),
+            path_put_secret: PATH_PUT_SECRET.to_string(),
+            path_delete_secret: PATH_DELETE_SECRET.to_string(),
+            path_list_tables: PATH_LIST_TABLES.to_string(),
+            path_list_keys: PATH_LIST_KEYS.to_string(),
+            path_watch: PATH_WATCH.to_string(),
+            discover: String::new(),
+            extra_headers: HashMap::new(),
+            accept: ACCEPT.to_string(),
+            compression: true,
+            user_agent: format!("vaultapi-client/{}", env!("CARGO_PKG_VERSION")),
+            request_id: String::new(),
+            page_size: PAGE_SIZE,
+            repeat_keys: false,
+            percent_encode: false,
+            cache_ttl: Duration::ZERO,
+            cipher: String::new(),
+            table_name: String::new(),
+            get_secret: String::new(),
+            get_secrets: String::new(),
+            get_table: String::new(),
+            put_secret: String::new(),
+            put_value: String::new(),
+            delete_secret: String::new(),
+            list_tables: false,
+            list_keys: false,
+            parallel: false,
+            parallel_limit: PARALLEL_LIMIT,
+            require_all: false,
+        }
+    }
+
+    /// Creates a builder that validates the retrieval settings up front, see `ConfigBuilder`.
+    ///
+    /// # Arguments
+    /// * `vault_server` - Base URL of the VaultAPI server, see `parse_vault_server`.
+    /// * `apikey` - APIkey to authenticate the server, and to derive the transit key.
+    ///
+    /// # Returns
+    /// * A `ConfigBuilder` with the default settings.
+    pub fn builder(vault_server: Url, apikey: String) -> ConfigBuilder {
+        ConfigBuilder {
+            config: Config::new(vault_server, apikey),
+        }
+    }
+
+    /// Collects the transit settings used to encrypt and decrypt payloads.
+    ///
+    /// # Returns
+    /// * A `TransitOptions` object.
+    pub fn transit_options(&self) -> TransitOptions {
+        TransitOptions {
+            key_length: self.transit_key_length,
+            time_bucket: self.transit_time_bucket,
+            tolerance: self.transit_tolerance,
+            cipher_suite: self.cipher_suite,
+            hash_algorithm: self.hash_algorithm,
+            base64_variant: self.base64_variant,
+            constant_time: self.transit_constant_time,
+            in_place: self.transit_in_place,
+            verify_hmac: self.transit_verify_hmac,
+            hmac_length: self.transit_hmac_length,
+            hmac_placement: self.transit_hmac_placement,
+            decompress: self.transit_decompress,
+            previous_apikey: Some(self.apikey_previous.clone()).filter(|previous| !previous.is_empty()),
+            tag_length: self.transit_tag_length,
+            epoch_override: self.transit_epoch_override,
+            derivation_order: self.transit_derivation_order,
+            derivation_separator: self.transit_derivation_separator.to_string(),
+        }
+    }
+
+    /// Builds the URL of an endpoint relative to the server URL.
+    ///
+    /// # Arguments
+    /// * `path` - Path of the endpoint, eg: `get-secret`
+    ///
+    /// # Returns
+    /// * A `String` containing the endpoint URL.
+    pub fn endpoint(&self, path: &str) -> String {
+        format!("{}{}", self.vault_server, path.trim_start_matches('/'))
+    }
+
+    /// Selects the apikey for a table, falling back to the global apikey when the table has no mapping.
+    ///
+    /// # Arguments
+    /// * `table_name` - Name of the table.
+    ///
+    /// # Returns
+    /// * A reference to the apikey to authenticate the requests for the table, and to derive its transit key.
+    pub fn apikey_for(&self, table_name: &str) -> &String {
+        self.table_apikeys.get(table_name).unwrap_or(&self.apikey)
+    }
+
+    /// Selects the additional authenticated data the ciphertext of a table is bound to.
+    ///
+    /// # Arguments
+    /// * `table_name` - Name of the table the ciphertext belongs to.
+    ///
+    /// # Returns
+    /// * An `Option` containing the table name bytes, prefixed with `<namespace>/` when a namespace is set,
+    ///   if `transit_aad` is enabled. `None` otherwise.
+    pub fn aad(&self, table_name: &str) -> Option<Vec<u8>> {
+        if !self.transit_aad {
+            return None;
+        }
+        if self.namespace.is_empty() {
+            Some(table_name.as_bytes().to_vec())
+        } else {
+            Some(format!("{}/{}", self.namespace, table_name).into_bytes())
+        }
+    }
+}
+
+/// Builder for `Config` that enforces the invariants of `server_connection` when built, instead of failing
+/// at request construction.
+pub struct ConfigBuilder {
+    config: Config,
+}
+
+impl ConfigBuilder {
+    /// Sets the table to retrieve the secrets from.
+    pub fn table_name(mut self, table_name: &str) -> Self {
+        self.config.table_name = table_name.to_string();
+        self
+    }
+
+    /// Retrieves a single secret from the table.
+    pub fn get_secret(mut self, key: &str) -> Self {
+        self.config.get_secret = key.to_string();
+        self
+    }
+
+    /// Retrieves multiple secrets from the table.
+    pub fn get_secrets(mut self, keys: &[&str]) -> Self {
+        self.config.get_secrets = keys.join(",");
+        self
+    }
+
+    /// Retrieves all the secrets in a table.
+    pub fn get_table(mut self, table_name: &str) -> Self {
+        self.config.get_table = table_name.to_string();
+        self
+    }
+
+    /// Adjusts any other setting, eg: `|config| config.retries = 5`.
+    pub fn configure<F: FnOnce(&mut Config)>(mut self, configure: F) -> Self {
+        configure(&mut self.config);
+        self
+    }
+
+    /// Validates that exactly one of `get_secret`, `get_secrets` and `get_table` is set, along with a table name.
+    ///
+    /// # Returns
+    /// * A `Result<Config, ConfigError>` containing the config, or the violated invariant.
+    pub fn build(self) -> Result<Config, ConfigError> {
+        let config = self.config;
+        let operations: Vec<&'static str> = [
+            ("get_secret", &config.get_secret),
+            ("get_secrets", &config.get_secrets),
+            ("get_table", &config.get_table),
+        ]
+            .into_iter()
+            .filter(|(_, value)| !value.is_empty())
+            .map(|(name, _)| name)
+            .collect();
+        match operations.len() {
+            0 => return Err(ConfigError::MissingOperation),
+            1 => {}
+            _ => return Err(ConfigError::ConflictingOperations(operations)),
+        }
+        if config.table_name.is_empty() && config.get_table.is_empty() {
+            return Err(ConfigError::MissingTable);
+        }
+        Ok(config)
+    }
+}
+
+/// Parses the VaultAPI server URL, ensuring it can be used as a base for the endpoint paths.
+///
+/// # Arguments
+/// * `value` - Server URL, eg: `http://0.0.0.0:8080` or `https://vault.example.com/api/`
+///
+/// # Returns
+/// * A `Result<Url, String>` containing the URL with a trailing slash, or an error message if the scheme isn't
+///   http or https, or if the URL has a query or a fragment the endpoint paths can't be appended after.
+pub fn parse_vault_server(value: &str) -> Result<Url, String> {
+    let mut url = match Url::parse(value) {
+        Ok(url) => url,
+        Err(err) => return Err(format!("Failed to parse vault address '{}': {}", value, err)),
+    };
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err(format!(
+            "Unsupported scheme '{}' in vault address '{}', expected http or https", url.scheme(), value
+        ));
+    }
+    if url.query().is_some() || url.fragment().is_some() {
+        return Err(format!("Vault address '{}' can't have a query or a fragment", value));
+    }
+    if !url.path().ends_with('/') {
+        let path = format!("{}/", url.path());
+        url.set_path(&path);
+    }
+    Ok(url)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vault_server_accepts_http_and_https() {
+        assert_eq!(parse_vault_server("http://0.0.0.0:8080/").unwrap().as_str(), "http://0.0.0.0:8080/");
+        assert_eq!(parse_vault_server("https://vault.example.com/").unwrap().as_str(), "https://vault.example.com/");
+        assert_eq!(parse_vault_server("HTTPS://Vault.Example.com").unwrap().as_str(), "https://vault.example.com/");
+    }
+
+    #[test]
+    fn vault_server_rejects_other_schemes() {
+        for value in ["ftp://vault.example.com/", "file:///etc/vault", "ws://vault.example.com", "unix:/run/vault.sock"] {
+            let err = parse_vault_server(value).unwrap_err();
+            assert!(err.starts_with("Unsupported scheme"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn vault_server_rejects_an_unparsable_address() {
+        for value in ["", "vault.example.com", "http://", "http://[::1"] {
+            let err = parse_vault_server(value).unwrap_err();
+            assert!(err.starts_with("Failed to parse vault address"), "{}: {}", value, err);
+        }
+    }
+
+    #[test]
+    fn vault_server_gets_a_trailing_slash() {
+        assert_eq!(parse_vault_server("http://0.0.0.0:8080").unwrap().as_str(), "http://0.0.0.0:8080/");
+        assert_eq!(parse_vault_server("https://vault.example.com/api").unwrap().as_str(), "https://vault.example.com/api/");
+        assert_eq!(parse_vault_server("https://vault.example.com/api/").unwrap().as_str(), "https://vault.example.com/api/");
+        let config = Config::new(parse_vault_server("https://vault.example.com/api").unwrap(), "key".to_string());
+        assert_eq!(config.endpoint("/get-secret"), "https://vault.example.com/api/get-secret");
+    }
+
+    #[test]
+    fn vault_server_rejects_a_query_or_a_fragment() {
+        for value in [
+            "https://vault.example.com/api?token=1",
+            "https://vault.example.com/?",
+            "https://vault.example.com/api#secrets",
+            "https://vault.example.com/#",
+            "http://0.0.0.0:8080?a=1#b",
+        ] {
+            assert_eq!(
+                parse_vault_server(value).unwrap_err(),
+                format!("Vault address '{}' can't have a query or a fragment", value)
+            );
+        }
+    }
+}
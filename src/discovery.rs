@@ -0,0 +1,97 @@
+use crate::config::Config;
+use crate::error::Error;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+
+/// Discovery documents fetched so far, keyed by their URL, reused for the lifetime of the process.
+static DISCOVERED: OnceLock<Mutex<HashMap<String, Discovery>>> = OnceLock::new();
+
+/// Actual paths of the routes, keyed by their default name, eg: `"get-secret": "v2/secrets/get"`.
+///
+/// Routes missing from the document keep the configured paths.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Endpoints {
+    pub get_secret: Option<String>,
+    pub get_secrets: Option<String>,
+    pub get_table: Option<String>,
+    pub put_secret: Option<String>,
+    pub delete_secret: Option<String>,
+    pub list_tables: Option<String>,
+    pub list_keys: Option<String>,
+    pub watch: Option<String>,
+}
+
+/// Discovery document served by dynamic environments, listing the paths of the routes on the configured server.
+///
+/// eg: `{"endpoints": {"get-secret": "v2/secrets/get"}}`
+///
+/// The document is fetched without authentication, so it can't redirect the requests to another server,
+/// which would receive the apikey. Any other field, such as a `server`, is ignored.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct Discovery {
+    #[serde(default)]
+    pub endpoints: Endpoints,
+}
+
+impl Discovery {
+    /// Overrides the endpoint paths of the config with the discovered ones, relative to the configured server.
+    ///
+    /// # Arguments
+    /// * `config` - Config to update.
+    pub fn apply(&self, config: &mut Config) {
+        let paths = [
+            (&self.endpoints.get_secret, &mut config.path_get_secret),
+            (&self.endpoints.get_secrets, &mut config.path_get_secrets),
+            (&self.endpoints.get_table, &mut config.path_get_table),
+            (&self.endpoints.put_secret, &mut config.path_put_secret),
+            (&self.endpoints.delete_secret, &mut config.path_delete_secret),
+            (&self.endpoints.list_tables, &mut config.path_list_tables),
+            (&self.endpoints.list_keys, &mut config.path_list_keys),
+            (&self.endpoints.watch, &mut config.path_watch),
+        ];
+        for (discovered, path) in paths {
+            if let Some(discovered) = discovered {
+                log::debug!("Discovered path {} in place of {}", discovered, path);
+                *path = discovered.clone();
+            }
+        }
+    }
+}
+
+/// Fetches the discovery document, or returns the one already fetched from the same URL by this process.
+///
+/// The document is requested without the apikey, since it is only used to locate the server.
+///
+/// # Arguments
+/// * `http` - HTTP client to send the request with.
+/// * `url` - URL of the discovery document.
+///
+/// # Returns
+/// * A `Result<Discovery, Error>` containing the discovered server URL and paths.
+pub async fn discover(http: &reqwest::Client, url: &str) -> Result<Discovery, Error> {
+    let discovered = DISCOVERED.get_or_init(|| Mutex::new(HashMap::new()));
+    if let Some(discovery) = discovered.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).get(url) {
+        log::debug!("Reusing the discovery document from {}", url);
+        return Ok(discovery.clone());
+    }
+    let response = match http.get(url).send().await {
+        Ok(response) => response,
+        Err(err) => return Err(Error::Request(format!("Failed to fetch the discovery document from {}: {}", url, err))),
+    };
+    let status = response.status();
+    if !status.is_success() {
+        return Err(Error::Status {
+            code: status.as_u16(),
+            message: format!("Discovery document at {} responded with {}", url, status),
+        });
+    }
+    let discovery: Discovery = match response.json().await {
+        Ok(discovery) => discovery,
+        Err(err) => return Err(Error::Response(format!("Unexpected discovery document from {}: {}", url, err))),
+    };
+    log::info!("Fetched the discovery document from {}", url);
+    discovered.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).insert(url.to_string(), discovery.clone());
+    Ok(discovery)
+}
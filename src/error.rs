@@ -0,0 +1,315 @@
+use crate::decipher::PayloadCompression;
+use std::fmt;
+use std::time::Duration;
+
+/// Errors that can occur while decrypting a transit payload.
+#[derive(Debug)]
+pub enum DecryptError {
+    /// System clock is set before the UNIX epoch, by the given duration.
+    SystemTimeBeforeEpoch(Duration),
+    /// Transit time bucket is zero seconds long.
+    ZeroTimeBucket,
+    /// Ciphertext is not valid base64.
+    Base64Decode(base64::DecodeError),
+    /// Ciphertext is shorter than the nonce.
+    CiphertextTooShort,
+    /// Ciphertext is too short to carry the authentication tag after the nonce.
+    MissingAuthTag,
+    /// Transit key length isn't supported by the cipher suite.
+    UnsupportedKeyLength(usize),
+    /// Authentication tag length isn't supported by the cipher suite, eg: a truncated tag.
+    UnsupportedTagLength(usize),
+    /// HMAC length isn't supported by HMAC-SHA256.
+    UnsupportedHmacLength(usize),
+    /// Hash algorithm produces fewer bytes than the transit key length.
+    InsufficientDigest { digest_length: usize, key_length: usize },
+    /// AES-GCM transit key could not be created from the derived bytes.
+    KeyCreation,
+    /// ChaCha20-Poly1305 transit key could not be created from the derived bytes.
+    ChaChaKeyCreation,
+    /// HMAC over the ciphertext doesn't match the key of any candidate bucket.
+    HmacMismatch,
+    /// Nonce could not be created from the ciphertext.
+    NonceCreation,
+    /// Ciphertext could not be decrypted with any candidate key.
+    DecryptionFailed,
+    /// Decrypted data could not be decompressed by the configured compression.
+    Decompress { compression: PayloadCompression, source: std::io::Error },
+    /// Decrypted data is not valid JSON.
+    JsonParse(serde_json::Error),
+    /// Reading the ciphertext or writing the decrypted data failed.
+    Io(std::io::Error),
+}
+
+impl fmt::Display for DecryptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecryptError::SystemTimeBeforeEpoch(behind) => write!(
+                f, "System time is {:?} before the UNIX epoch, check the clock of the host", behind
+            ),
+            DecryptError::ZeroTimeBucket => write!(f, "Transit time bucket must be at least 1 second"),
+            DecryptError::Base64Decode(_) => write!(f, "Failed to decode ciphertext"),
+            DecryptError::CiphertextTooShort => write!(f, "Ciphertext is too short"),
+            DecryptError::MissingAuthTag => write!(f, "Ciphertext missing authentication tag"),
+            DecryptError::UnsupportedKeyLength(length) => write!(
+                f, "Unsupported transit key length: {} bytes, expected 16 or 32 for AES-GCM and 32 for ChaCha20-Poly1305", length
+            ),
+            DecryptError::UnsupportedTagLength(length) => write!(
+                f, "Unsupported authentication tag length: {} bytes, AES-GCM and ChaCha20-Poly1305 require 16", length
+            ),
+            DecryptError::UnsupportedHmacLength(length) => write!(
+                f, "Unsupported HMAC length: {} bytes, expected between 16 and 32", length
+            ),
+            DecryptError::InsufficientDigest { digest_length, key_length } => write!(
+                f, "Hash algorithm produces {} bytes, fewer than the {} byte transit key", digest_length, key_length
+            ),
+            DecryptError::KeyCreation => write!(f, "Failed to create AES key"),
+            DecryptError::ChaChaKeyCreation => write!(f, "Failed to create ChaCha20-Poly1305 key"),
+            DecryptError::HmacMismatch => write!(f, "Ciphertext failed the HMAC integrity check, it may have been tampered with"),
+            DecryptError::NonceCreation => write!(f, "Failed to create nonce"),
+            DecryptError::DecryptionFailed => write!(f, "Failed to decrypt data"),
+            DecryptError::Decompress { compression, source } => write!(
+                f, "Failed to decompress decrypted data as {}: {}", compression, source
+            ),
+            DecryptError::JsonParse(_) => write!(f, "Failed to parse decrypted data as JSON"),
+            DecryptError::Io(err) => write!(f, "Failed to stream data: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for DecryptError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DecryptError::Base64Decode(err) => Some(err),
+            DecryptError::Decompress { source, .. } => Some(source),
+            DecryptError::JsonParse(err) => Some(err),
+            DecryptError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Errors that can occur while encrypting a transit payload.
+#[derive(Debug)]
+pub enum EncryptError {
+    /// System clock is set before the UNIX epoch, by the given duration.
+    SystemTimeBeforeEpoch(Duration),
+    /// Transit time bucket is zero seconds long.
+    ZeroTimeBucket,
+    /// Payload could not be serialized as JSON.
+    JsonSerialize(serde_json::Error),
+    /// Random nonce could not be generated.
+    NonceGeneration,
+    /// Transit key length isn't supported by the cipher suite.
+    UnsupportedKeyLength(usize),
+    /// Authentication tag length isn't supported by the cipher suite, eg: a truncated tag.
+    UnsupportedTagLength(usize),
+    /// HMAC length isn't supported by HMAC-SHA256.
+    UnsupportedHmacLength(usize),
+    /// Hash algorithm produces fewer bytes than the transit key length.
+    InsufficientDigest { digest_length: usize, key_length: usize },
+    /// AES-GCM transit key could not be created from the derived bytes.
+    KeyCreation,
+    /// ChaCha20-Poly1305 transit key could not be created from the derived bytes.
+    ChaChaKeyCreation,
+    /// Payload could not be encrypted.
+    EncryptionFailed,
+}
+
+impl fmt::Display for EncryptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncryptError::SystemTimeBeforeEpoch(behind) => write!(
+                f, "System time is {:?} before the UNIX epoch, check the clock of the host", behind
+            ),
+            EncryptError::ZeroTimeBucket => write!(f, "Transit time bucket must be at least 1 second"),
+            EncryptError::JsonSerialize(_) => write!(f, "Failed to serialize payload as JSON"),
+            EncryptError::NonceGeneration => write!(f, "Failed to generate nonce"),
+            EncryptError::UnsupportedKeyLength(length) => write!(
+                f, "Unsupported transit key length: {} bytes, expected 16 or 32 for AES-GCM and 32 for ChaCha20-Poly1305", length
+            ),
+            EncryptError::UnsupportedTagLength(length) => write!(
+                f, "Unsupported authentication tag length: {} bytes, AES-GCM and ChaCha20-Poly1305 require 16", length
+            ),
+            EncryptError::UnsupportedHmacLength(length) => write!(
+                f, "Unsupported HMAC length: {} bytes, expected between 16 and 32", length
+            ),
+            EncryptError::InsufficientDigest { digest_length, key_length } => write!(
+                f, "Hash algorithm produces {} bytes, fewer than the {} byte transit key", digest_length, key_length
+            ),
+            EncryptError::KeyCreation => write!(f, "Failed to create AES key"),
+            EncryptError::ChaChaKeyCreation => write!(f, "Failed to create ChaCha20-Poly1305 key"),
+            EncryptError::EncryptionFailed => write!(f, "Failed to encrypt data"),
+        }
+    }
+}
+
+impl std::error::Error for EncryptError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            EncryptError::JsonSerialize(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Invariants violated while building a `Config` with `ConfigBuilder`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    /// None of `get_secret`, `get_secrets` or `get_table` is set.
+    MissingOperation,
+    /// More than one of `get_secret`, `get_secrets` and `get_table` is set.
+    ConflictingOperations(Vec<&'static str>),
+    /// Table name is required to retrieve the secret.
+    MissingTable,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::MissingOperation => write!(f, "One of get_secret, get_secrets or get_table is required"),
+            ConfigError::ConflictingOperations(names) => write!(
+                f, "Only one of get_secret, get_secrets or get_table can be set, found {}", names.join(", ")
+            ),
+            ConfigError::MissingTable => write!(f, "Table name is mandatory to retrieve the secret"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Appends the ID of the request to an error message, so client failures can be correlated with the server logs.
+///
+/// # Arguments
+/// * `message` - Error message.
+/// * `request_id` - Value of the `X-Request-ID` header sent, empty if unknown.
+///
+/// # Returns
+/// * A `String` containing the message, followed by the request ID when known.
+pub(crate) fn with_request_id(message: impl fmt::Display, request_id: &str) -> String {
+    if request_id.is_empty() {
+        message.to_string()
+    } else {
+        format!("{} (request ID: {})", message, request_id)
+    }
+}
+
+/// Reasons a request could not reach the VaultAPI server, to tell a misconfigured server from a network outage.
+///
+/// Each variant carries the `X-Request-ID` of the failed request, empty if unknown.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionError {
+    /// Host name of the server could not be resolved.
+    Dns { url: String, message: String, request_id: String },
+    /// Server host is reachable, but nothing is listening on the port.
+    Refused { url: String, message: String, request_id: String },
+    /// TLS handshake failed, eg: an untrusted certificate or a plain HTTP server behind an `https` URL.
+    Tls { url: String, message: String, request_id: String },
+    /// Server didn't respond within the configured timeout.
+    Timeout { url: String, timeout: Duration, request_id: String },
+    /// Request failed for any other reason, eg: the connection was reset.
+    Other { url: String, message: String, request_id: String },
+}
+
+impl fmt::Display for ConnectionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (message, request_id) = match self {
+            ConnectionError::Dns { url, message, request_id } => {
+                (format!("Failed to resolve the host of {}: {}", url, message), request_id)
+            }
+            ConnectionError::Refused { url, message, request_id } => {
+                (format!("Connection to {} refused: {}", url, message), request_id)
+            }
+            ConnectionError::Tls { url, message, request_id } => {
+                (format!("TLS handshake with {} failed: {}", url, message), request_id)
+            }
+            ConnectionError::Timeout { url, timeout, request_id } => {
+                (format!("Request to {} timed out after {}s", url, timeout.as_secs()), request_id)
+            }
+            ConnectionError::Other { url, message, request_id } => {
+                (format!("Failed to fetch data from {}: {}", url, message), request_id)
+            }
+        };
+        write!(f, "{}", with_request_id(message, request_id))
+    }
+}
+
+impl std::error::Error for ConnectionError {}
+
+/// Errors that can occur while retrieving a secret from the VaultAPI server.
+#[derive(Debug)]
+pub enum Error {
+    /// Required parameters are missing or conflicting.
+    Config(String),
+    /// Request could not be sent, or its response could not be read.
+    Request(String),
+    /// Server could not be reached.
+    Connection(ConnectionError),
+    /// Server responded with an unexpected payload.
+    Response(String),
+    /// Server responded with a non-2xx status code.
+    Status { code: u16, message: String },
+    /// Server didn't return some of the requested keys, with `require_all`.
+    MissingKeys(Vec<String>),
+    /// Retrieved secrets could not be written to disk.
+    Io(String),
+    /// Transit payload could not be decrypted.
+    Decrypt(DecryptError),
+    /// Secret could not be encrypted for transit.
+    Encrypt(EncryptError),
+    /// Decrypted secret doesn't match the requested type.
+    Deserialize(serde_json::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Config(message) => write!(f, "{}", message),
+            Error::Request(message) => write!(f, "{}", message),
+            Error::Connection(err) => write!(f, "{}", err),
+            Error::Response(message) => write!(f, "{}", message),
+            Error::Status { message, .. } => write!(f, "{}", message),
+            Error::MissingKeys(keys) => write!(f, "missing keys: [{}]", keys.join(", ")),
+            Error::Io(message) => write!(f, "{}", message),
+            Error::Decrypt(err) => write!(f, "{}", err),
+            Error::Encrypt(err) => write!(f, "{}", err),
+            Error::Deserialize(err) => write!(f, "Failed to deserialize the secret: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Connection(err) => Some(err),
+            Error::Decrypt(err) => Some(err),
+            Error::Encrypt(err) => Some(err),
+            Error::Deserialize(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<ConfigError> for Error {
+    fn from(err: ConfigError) -> Self {
+        Error::Config(err.to_string())
+    }
+}
+
+impl From<ConnectionError> for Error {
+    fn from(err: ConnectionError) -> Self {
+        Error::Connection(err)
+    }
+}
+
+impl From<DecryptError> for Error {
+    fn from(err: DecryptError) -> Self {
+        Error::Decrypt(err)
+    }
+}
+
+impl From<EncryptError> for Error {
+    fn from(err: EncryptError) -> Self {
+        Error::Encrypt(err)
+    }
+}
@@ -1,24 +1,134 @@
 #![allow(rustdoc::bare_urls)]
 #![doc = include_str!("../README.md")]
+#[cfg(feature = "cli")]
 pub mod parser;
+#[cfg(feature = "cli")]
 pub mod constant;
+pub mod config;
+pub mod client;
+pub mod discovery;
+mod cache;
+mod atomic;
+pub mod metrics;
+pub mod auth;
 pub mod request;
 pub mod decipher;
+pub mod error;
+#[cfg(feature = "cli")]
+pub mod logger;
+#[cfg(feature = "cli")]
+pub mod output;
+#[cfg(feature = "test-server")]
+pub mod test_server;
 
 use serde_json::Value;
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
 
 /// Decrypts the ciphered text into JSON object.
 ///
 /// # Returns
-/// * A `Result<Value, String>` containing deciphered content.
-pub fn decrypt_vault_secret(config: parser::Config) -> Result<Value, String> {
+/// * A `Result<Value, Error>` containing deciphered content.
+pub fn decrypt_vault_secret(config: config::Config) -> Result<Value, error::Error> {
     if config.cipher.is_empty() {
-        return request::server_connection(&config);
+        return retrieve_vault_secret(&client::VaultClient::new(config)?);
     }
     decipher::transit_decrypt(
-        &config.apikey,
+        config.apikey_for(&config.table_name),
         &config.cipher,
-        config.transit_key_length,
-        config.transit_time_bucket,
-    )
+        &config.transit_options(),
+        config.aad(&config.table_name).as_deref(),
+    ).map_err(error::Error::from)
+}
+
+/// Decrypts one ciphertext per line, writing an NDJSON result for each, without contacting the server.
+///
+/// Each result carries its 1-based `line` number, and either the decrypted `value` or the `error` that
+/// failed it, so a bad line doesn't stop the rest of the dump. Blank lines are skipped.
+///
+/// # Arguments
+/// * `config` - Transit settings, apikey and table name the ciphertexts were encrypted with.
+/// * `reader` - Source of the ciphertexts, eg: an exported dump.
+/// * `writer` - Destination of the NDJSON results.
+///
+/// # Returns
+/// * A `Result<(usize, usize), Error>` containing the number of decrypted and failed lines,
+///   or an error if reading the input or writing the results failed.
+pub fn decrypt_batch<R: BufRead, W: Write>(
+    config: &config::Config,
+    reader: R,
+    writer: &mut W,
+) -> Result<(usize, usize), error::Error> {
+    let apikey = config.apikey_for(&config.table_name);
+    let options = config.transit_options();
+    let aad = config.aad(&config.table_name);
+    let (mut decrypted, mut failed) = (0, 0);
+    for (index, line) in reader.lines().enumerate() {
+        let line = line.map_err(|err| error::Error::Io(format!("Failed to read line {}: {}", index + 1, err)))?;
+        let ciphertext = line.trim().to_string();
+        if ciphertext.is_empty() {
+            continue;
+        }
+        let result = match decipher::transit_decrypt(apikey, &ciphertext, &options, aad.as_deref()) {
+            Ok(value) => {
+                decrypted += 1;
+                serde_json::json!({ "line": index + 1, "value": value })
+            }
+            Err(err) => {
+                failed += 1;
+                log::warn!("Failed to decrypt line {}: {}", index + 1, err);
+                serde_json::json!({ "line": index + 1, "error": err.to_string() })
+            }
+        };
+        writeln!(writer, "{}", result)
+            .map_err(|err| error::Error::Io(format!("Failed to write the result of line {}: {}", index + 1, err)))?;
+    }
+    writer.flush().map_err(|err| error::Error::Io(format!("Failed to write the results: {}", err)))?;
+    Ok((decrypted, failed))
+}
+
+/// Retrieves the secrets requested in the client's config from the server.
+///
+/// # Arguments
+/// * `vault` - Client to send the requests with, which can be reused across retrievals.
+///
+/// # Returns
+/// * A `Result<Value, Error>` containing deciphered content.
+pub fn retrieve_vault_secret(vault: &client::VaultClient) -> Result<Value, error::Error> {
+    if vault.config.parallel && !vault.config.get_secrets.is_empty() {
+        let results = vault.get_secrets_parallel()?;
+        if vault.config.require_all {
+            // Each key is requested on its own, so a missing key is one the server couldn't find
+            let missing: Vec<String> = request::normalize_keys(&vault.config.get_secrets)?
+                .into_iter()
+                .filter(|key| matches!(results.get(key), Some(Err(error::Error::Status { code: 404, .. }))))
+                .collect();
+            if !missing.is_empty() {
+                return Err(error::Error::MissingKeys(missing));
+            }
+        }
+        return Ok(merge_parallel_results(results));
+    }
+    vault.server_connection()
+}
+
+/// Merges the per-key results of a parallel retrieval into a single JSON object.
+///
+/// # Arguments
+/// * `results` - Deciphered content or error for each key.
+///
+/// # Returns
+/// * A `Value` object mapping each key to its secret, or to an `error` object if the retrieval failed.
+fn merge_parallel_results(results: HashMap<String, Result<Value, error::Error>>) -> Value {
+    let mut merged = serde_json::Map::new();
+    for (key, result) in results {
+        let value = match result {
+            // Each secret is returned as an object keyed by its name
+            Ok(Value::Object(mut secret)) if secret.contains_key(&key) => secret.remove(&key).unwrap_or_default(),
+            Ok(secret) => secret,
+            Err(err) => serde_json::json!({ "error": err.to_string() }),
+        };
+        merged.insert(key, value);
+    }
+    Value::Object(merged)
 }
@@ -0,0 +1,91 @@
+use std::io::Write;
+use std::str::FromStr;
+
+/// Formats in which log lines can be emitted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("Unsupported log format: {}, expected one of text, json", value)),
+        }
+    }
+}
+
+/// Replaces every occurrence of the given secrets with `***`.
+///
+/// # Arguments
+/// * `message` - Formatted log message.
+/// * `secrets` - Values that must never appear in the logs, eg: the apikey.
+///
+/// # Returns
+/// * A `String` with the secrets masked.
+pub fn redact(message: &str, secrets: &[String]) -> String {
+    let mut redacted = message.to_string();
+    for secret in secrets.iter().filter(|secret| !secret.is_empty()) {
+        redacted = redacted.replace(secret.as_str(), "***");
+    }
+    redacted
+}
+
+/// Initializes the logger for the crate.
+///
+/// # Arguments
+/// * `debug` - Boolean flag to enable debug level logging.
+/// * `quiet` - Boolean flag to log only the errors.
+/// * `utc` - Boolean flag to use UTC timestamps instead of local time.
+/// * `log_format` - Human-readable text, or one JSON object per line for log aggregators.
+/// * `crate_name` - Name of the crate, used as the logging target.
+/// * `secrets` - Values masked from every log line regardless of the target, eg: the apikey.
+///
+/// ## See Also
+/// `RUST_LOG` environment variable overrides the log level when set.
+pub fn init_logger(
+    debug: bool,
+    quiet: bool,
+    utc: bool,
+    log_format: LogFormat,
+    crate_name: &String,
+    secrets: Vec<String>,
+) {
+    let level = if quiet { "error" } else if debug { "debug" } else { "info" };
+    let mut builder = env_logger::Builder::from_env(
+        env_logger::Env::default().default_filter_or(format!("{}={}", crate_name, level))
+    );
+    builder.format(move |buf, record| {
+        let message = redact(&record.args().to_string(), &secrets);
+        match log_format {
+            LogFormat::Json => {
+                let timestamp = if utc {
+                    chrono::Utc::now().to_rfc3339()
+                } else {
+                    chrono::Local::now().to_rfc3339()
+                };
+                let line = serde_json::json!({
+                    "timestamp": timestamp,
+                    "level": record.level().to_string(),
+                    "target": record.target(),
+                    "message": message,
+                });
+                writeln!(buf, "{}", line)
+            }
+            LogFormat::Text if utc => {
+                writeln!(buf, "[{} {:<5} {}] {}", buf.timestamp(), record.level(), record.target(), message)
+            }
+            LogFormat::Text => {
+                let local_time = chrono::Local::now().format("%Y-%m-%d %H:%M:%S");
+                writeln!(buf, "[{} {} {}] - {}", local_time, record.level(), record.target(), message)
+            }
+        }
+    });
+    builder.init();
+}
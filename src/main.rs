@@ -1,13 +1,282 @@
+use serde_json::Value;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use vaultapi::error::Error;
+use vaultapi::output::OutputFormat;
+use vaultapi::parser::{Mode, Output};
+
+/// Maps an error to the exit code of its failure class, so scripts can branch on it.
+///
+/// # Arguments
+/// * `err` - Error that stopped the command.
+///
+/// # Returns
+/// * An `i32` exit code, see the README for the full list.
+fn exit_code(err: &Error) -> i32 {
+    match err {
+        Error::Config(_) => 2,
+        Error::Request(_) | Error::Connection(_) => 3,
+        Error::Status { code: 401 | 403, .. } => 4,
+        Error::Status { code: 404, .. } | Error::MissingKeys(_) => 6,
+        Error::Status { code: 500.., .. } => 3,
+        Error::Decrypt(_) | Error::Encrypt(_) | Error::Deserialize(_) => 5,
+        _ => 1,
+    }
+}
+
+/// Names the failure class of an error, for `--verify`.
+///
+/// # Arguments
+/// * `err` - Error that stopped the command.
+///
+/// # Returns
+/// * A `&str` matching the exit code of the error, eg: `decrypt` for `5`.
+fn category(err: &Error) -> &'static str {
+    match exit_code(err) {
+        2 => "config",
+        3 => "network",
+        4 => "auth",
+        5 => "decrypt",
+        6 => "not-found",
+        _ => "error",
+    }
+}
+
+/// Writes the decrypted secrets to the requested destination.
+///
+/// # Arguments
+/// * `value` - Decrypted JSON value.
+/// * `output` - Destination and format of the secrets.
+///
+/// # Returns
+/// * A `Result<(), String>` indicating success or an error message.
+fn write_output(value: &Value, output: &Output) -> Result<(), String> {
+    if output.log_secrets {
+        log::debug!("Decrypted payload: {}", value);
+    }
+    let selected;
+    let mut value = value;
+    if !output.select.is_empty() {
+        selected = vaultapi::output::select(value, &output.select)?;
+        value = &selected;
+    }
+    let flattened;
+    if output.flatten {
+        flattened = vaultapi::output::flatten(value)?;
+        value = &flattened;
+    }
+    if !output.write_env.is_empty() {
+        let path = std::path::Path::new(&output.write_env);
+        vaultapi::output::write_env_file(value, path)?;
+        log::info!("Secrets written to {}", path.display());
+    } else if !output.keyring_store.is_empty() {
+        let count = vaultapi::output::store_in_keyring(value, &output.keyring_store)?;
+        log::info!("{} entries written to the keyring service {}", count, output.keyring_store);
+    } else if !output.systemd_creds.is_empty() {
+        let path = std::path::Path::new(&output.systemd_creds);
+        let count = vaultapi::output::write_credentials(value, path)?;
+        log::info!("{} credentials written to {}", count, path.display());
+    } else if !output.output.is_empty() {
+        let path = std::path::Path::new(&output.output);
+        vaultapi::output::write_atomic_with_mode(&formatted(value, output)?, path, Some(output.output_mode))?;
+        log::info!("Secrets written to {}", path.display());
+    } else if !output.raw && output.format == OutputFormat::Ndjson {
+        vaultapi::output::write_ndjson(value, &mut std::io::stdout().lock())?;
+    } else {
+        print!("{}", formatted(value, output)?);
+    }
+    Ok(())
+}
+
+/// Formats the decrypted secrets for stdout or the `--output` file, with `--raw` taking precedence over `--format`.
+///
+/// # Arguments
+/// * `value` - Decrypted JSON value.
+/// * `output` - Format of the secrets.
+///
+/// # Returns
+/// * A `Result<String, String>` containing the formatted secrets with a trailing newline, or an error message.
+fn formatted(value: &Value, output: &Output) -> Result<String, String> {
+    if output.raw {
+        return Ok(format!("{}\n", vaultapi::output::raw_value(value)?));
+    }
+    match output.format {
+        OutputFormat::Ndjson => {
+            let mut buffer = Vec::new();
+            vaultapi::output::write_ndjson(value, &mut buffer)?;
+            String::from_utf8(buffer).map_err(|err| err.to_string())
+        }
+        OutputFormat::Shell => vaultapi::output::shell_exports(value, output.shell_keys),
+        format => Ok(format!("{}\n", vaultapi::output::render(value, format, output.pretty)?)),
+    }
+}
+
+/// Re-fetches the secrets at every interval until interrupted, writing them out only when they change.
+///
+/// Failures are logged and retried at the next tick instead of exiting, so a sidecar survives a server restart.
+/// On Ctrl-C, the write in progress is completed before returning, so the output file is never left half written.
+///
+/// # Arguments
+/// * `vault` - Client reused across the retrievals.
+/// * `interval` - Time between the retrievals.
+/// * `output` - Destination and format of the secrets.
+fn watch(vault: vaultapi::client::VaultClient, interval: std::time::Duration, output: &Output) {
+    let mut last_hash = None;
+    while !vaultapi::output::interrupted() {
+        match vaultapi::retrieve_vault_secret(&vault) {
+            Ok(value) => {
+                let mut hasher = DefaultHasher::new();
+                value.to_string().hash(&mut hasher);
+                let hash = hasher.finish();
+                if last_hash == Some(hash) {
+                    log::debug!("Secrets unchanged, skipping the write");
+                } else {
+                    match write_output(&value, output) {
+                        Ok(_) => last_hash = Some(hash),
+                        Err(err) => log::error!("{}, retrying in {}s", err, interval.as_secs()),
+                    }
+                }
+            }
+            Err(err) => log::error!("{}, retrying in {}s", err, interval.as_secs()),
+        }
+        // Sleep in short steps, to stop promptly when interrupted
+        let resume = std::time::Instant::now() + interval;
+        while !vaultapi::output::interrupted() && std::time::Instant::now() < resume {
+            std::thread::sleep(std::time::Duration::from_millis(100).min(interval));
+        }
+    }
+    log::info!("Stopped watching");
+}
+
+/// Writes out each update pushed by the server until interrupted, see `VaultClient::watch_remote`.
+///
+/// Updates that fail to decrypt or to be written are logged and skipped, so a single bad event doesn't end the stream.
+///
+/// # Arguments
+/// * `vault` - Client to open the stream with.
+/// * `output` - Destination and format of the secrets.
+///
+/// # Returns
+/// * A `Result<(), vaultapi::error::Error>` once interrupted, or the error the server rejected the stream with.
+fn watch_remote(vault: vaultapi::client::VaultClient, output: &Output) -> Result<(), vaultapi::error::Error> {
+    let table_name = vault.config.table_name.to_string();
+    vault.watch_remote(&table_name, |update| {
+        let written = update.map_err(|err| err.to_string()).and_then(|value| write_output(&value, output));
+        if let Err(err) = written {
+            log::error!("Skipping the update: {}", err);
+        }
+        !vaultapi::output::interrupted()
+    })?;
+    log::info!("Stopped watching");
+    Ok(())
+}
 
 fn main() {
     let metadata = vaultapi::constant::build_info();
-    let config = vaultapi::parser::arguments(&metadata);
-    match vaultapi::decrypt_vault_secret(config) {
+    let (config, output) = vaultapi::parser::arguments(&metadata);
+    vaultapi::logger::init_logger(
+        output.debug,
+        output.quiet,
+        output.utc,
+        output.log_format,
+        &metadata.crate_name,
+        [&config.apikey, &config.apikey_previous].into_iter().chain(config.table_apikeys.values()).cloned().collect(),
+    );
+    log::info!("vault address: {}", &config.vault_server);
+    match &output.mode {
+        Mode::Health => {
+            let info = vaultapi::client::VaultClient::new(config).and_then(|vault| vault.health());
+            match info {
+                Ok(info) => println!("{}", serde_json::json!(info)),
+                Err(err) => {
+                    eprintln!("{}", err);
+                    std::process::exit(exit_code(&err))
+                }
+            }
+            return;
+        }
+        Mode::DecryptBatch(path) => {
+            let file = match std::fs::File::open(path) {
+                Ok(file) => std::io::BufReader::new(file),
+                Err(err) => {
+                    eprintln!("Failed to read {}: {}", path, err);
+                    std::process::exit(2)
+                }
+            };
+            let mut stdout = std::io::BufWriter::new(std::io::stdout().lock());
+            match vaultapi::decrypt_batch(&config, file, &mut stdout) {
+                Ok((decrypted, 0)) => log::info!("{} lines decrypted", decrypted),
+                Ok((decrypted, failed)) => {
+                    log::warn!("{} lines decrypted, {} failed", decrypted, failed);
+                    std::process::exit(5)
+                }
+                Err(err) => {
+                    eprintln!("{}", err);
+                    std::process::exit(exit_code(&err))
+                }
+            }
+            return;
+        }
+        Mode::WatchRemote => {
+            if let Err(err) = vaultapi::output::handle_interrupts() {
+                eprintln!("{}", err);
+                std::process::exit(1)
+            }
+            if let Err(err) = vaultapi::client::VaultClient::new(config).and_then(|vault| watch_remote(vault, &output)) {
+                eprintln!("{}", err);
+                std::process::exit(exit_code(&err))
+            }
+            return;
+        }
+        Mode::Watch(interval) => {
+            if let Err(err) = vaultapi::output::handle_interrupts() {
+                eprintln!("{}", err);
+                std::process::exit(1)
+            }
+            match vaultapi::client::VaultClient::new(config) {
+                Ok(vault) => return watch(vault, *interval, &output),
+                Err(err) => {
+                    eprintln!("{}", err);
+                    std::process::exit(exit_code(&err))
+                }
+            }
+        }
+        Mode::Verify => {
+            // Only the outcome is printed, never the decrypted value
+            match vaultapi::decrypt_vault_secret(config) {
+                Ok(_) => println!("OK"),
+                Err(err) => {
+                    log::debug!("Verification failed: {}", err);
+                    println!("FAILED: {}", category(&err));
+                    std::process::exit(exit_code(&err))
+                }
+            }
+            return;
+        }
+        Mode::DryRun | Mode::Once => {}
+    }
+    let result = if output.mode == Mode::DryRun {
+        vaultapi::client::VaultClient::new(config).and_then(|vault| vault.describe())
+    } else {
+        vaultapi::decrypt_vault_secret(config)
+    };
+    match result {
         Ok(value) => {
-            println!("{}", value);
-        },
+            // Streamed output can be long-running when piped to a slow consumer
+            if output.format == OutputFormat::Ndjson {
+                if let Err(err) = vaultapi::output::handle_interrupts() {
+                    eprintln!("{}", err);
+                    std::process::exit(1)
+                }
+            }
+            if let Err(err) = write_output(&value, &output) {
+                eprintln!("{}", err);
+                std::process::exit(if vaultapi::output::interrupted() { 130 } else { 1 })
+            }
+        }
         Err(err) => {
-            println!("{}", err);
+            eprintln!("{}", err);
+            std::process::exit(exit_code(&err))
         }
     };
 }
@@ -0,0 +1,70 @@
+use std::time::Duration;
+
+/// Hook to observe the requests and decryptions made by a `VaultClient`, eg: to export them to Prometheus or statsd.
+///
+/// Every event defaults to a no-op, so implementations only need to override the ones they record.
+/// The methods are called inline, and should return quickly.
+pub trait Metrics: Send + Sync {
+    /// Called before each attempt of a request is sent.
+    ///
+    /// # Arguments
+    /// * `method` - HTTP method of the request.
+    /// * `url` - Request URL, without the query parameters.
+    fn request_started(&self, _method: &str, _url: &str) {}
+
+    /// Called after each attempt of a request has completed.
+    ///
+    /// # Arguments
+    /// * `method` - HTTP method of the request.
+    /// * `url` - Request URL, without the query parameters.
+    /// * `status` - Response status code, `None` if the request failed without a response.
+    /// * `duration` - Time taken to receive the response headers.
+    fn request_completed(&self, _method: &str, _url: &str, _status: Option<u16>, _duration: Duration) {}
+
+    /// Called before a request is retried after a transient failure.
+    ///
+    /// # Arguments
+    /// * `url` - Request URL, without the query parameters.
+    /// * `attempt` - Retry attempt number, starting at 1.
+    /// * `delay` - Time waited before the retry.
+    fn retry(&self, _url: &str, _attempt: u32, _delay: Duration) {}
+
+    /// Called after a transit payload is decrypted.
+    ///
+    /// # Arguments
+    /// * `success` - Whether the payload could be decrypted.
+    /// * `duration` - Time taken to decrypt the payload.
+    fn decrypt_completed(&self, _success: bool, _duration: Duration) {}
+}
+
+/// Metrics hook that discards all the events, used by default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopMetrics;
+
+impl Metrics for NoopMetrics {}
+
+/// Metrics hook that prints each event as a line to stderr.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StderrMetrics;
+
+impl Metrics for StderrMetrics {
+    fn request_started(&self, method: &str, url: &str) {
+        eprintln!("request_started method={} url={}", method, url);
+    }
+
+    fn request_completed(&self, method: &str, url: &str, status: Option<u16>, duration: Duration) {
+        let status = status.map(|code| code.to_string()).unwrap_or_else(|| "none".to_string());
+        eprintln!(
+            "request_completed method={} url={} status={} duration_ms={}",
+            method, url, status, duration.as_millis()
+        );
+    }
+
+    fn retry(&self, url: &str, attempt: u32, delay: Duration) {
+        eprintln!("retry url={} attempt={} delay_ms={}", url, attempt, delay.as_millis());
+    }
+
+    fn decrypt_completed(&self, success: bool, duration: Duration) {
+        eprintln!("decrypt_completed success={} duration_us={}", success, duration.as_micros());
+    }
+}
@@ -0,0 +1,611 @@
+use serde_json::Value;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Formats in which the decrypted secrets can be rendered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    #[default]
+    Json,
+    Yaml,
+    Env,
+    Ndjson,
+    Shell,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "json" => Ok(OutputFormat::Json),
+            "yaml" | "yml" => Ok(OutputFormat::Yaml),
+            "env" => Ok(OutputFormat::Env),
+            "ndjson" => Ok(OutputFormat::Ndjson),
+            "shell" => Ok(OutputFormat::Shell),
+            _ => Err(format!("Unsupported output format: {}, expected one of json, yaml, env, ndjson, shell", value)),
+        }
+    }
+}
+
+/// Handling of keys that aren't valid shell identifiers, for the shell output format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShellKeys {
+    /// Leaves the key out with a warning.
+    #[default]
+    Skip,
+    /// Replaces the invalid characters with `_`, and prefixes keys starting with a digit with `_`.
+    Sanitize,
+}
+
+impl FromStr for ShellKeys {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "skip" => Ok(ShellKeys::Skip),
+            "sanitize" => Ok(ShellKeys::Sanitize),
+            _ => Err(format!("Unsupported shell keys handling: {}, expected one of skip, sanitize", value)),
+        }
+    }
+}
+
+/// Installs a Ctrl-C handler, that asks the long-running modes to stop once the write in progress completes.
+///
+/// A second Ctrl-C exits immediately, eg: when stuck on a slow request.
+///
+/// # Returns
+/// * A `Result<(), String>` indicating success or an error message.
+pub fn handle_interrupts() -> Result<(), String> {
+    let result = ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            std::process::exit(130)
+        }
+        log::warn!("Interrupted, stopping after the current write. Press Ctrl-C again to exit immediately");
+    });
+    result.map_err(|err| format!("Failed to install the Ctrl-C handler: {}", err))
+}
+
+/// Checks if Ctrl-C was pressed, after `handle_interrupts` is installed.
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Quotes a value for a dotenv file, if it contains whitespace or special characters.
+///
+/// # Arguments
+/// * `value` - Value to quote.
+///
+/// # Returns
+/// * A `String` that can be safely written after `KEY=`.
+fn quote_env_value(value: &str) -> String {
+    let special = |c: char| c.is_whitespace() || "#\"'\\$`=".contains(c);
+    if !value.is_empty() && !value.contains(special) {
+        return value.to_string();
+    }
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('$', "\\$");
+    format!("\"{}\"", escaped)
+}
+
+/// Checks if a key can be written as a dotenv variable name, without spilling into the value or the next line.
+fn is_env_key(key: &str) -> bool {
+    !key.is_empty() && !key.starts_with('#') && !key.contains(|c: char| c == '=' || c.is_whitespace() || c.is_control())
+}
+
+/// Flattens the top-level JSON object into `KEY=value` lines.
+///
+/// Keys that are empty, start with `#`, or contain `=`, whitespace or control characters are left out with a warning,
+/// as they would inject other variables into the file, eg: `"A\nB": "1"` as `B=1`.
+///
+/// # Arguments
+/// * `secrets` - Decrypted JSON object.
+///
+/// # Returns
+/// * A `Result<String, String>` containing the dotenv content or an error message.
+pub fn env_lines(secrets: &Value) -> Result<String, String> {
+    let object = match secrets {
+        Value::Object(object) => object,
+        _ => return Err("Only JSON objects can be written as environment variables".to_string()),
+    };
+    let mut content = String::new();
+    for (key, value) in object {
+        if !is_env_key(key) {
+            log::warn!("Skipping {:?}, not a valid environment variable name", key);
+            continue;
+        }
+        // Non-string values are serialized compactly
+        let value = match value {
+            Value::String(text) => text.to_string(),
+            other => other.to_string(),
+        };
+        content.push_str(&format!("{}={}\n", key, quote_env_value(&value)));
+    }
+    Ok(content)
+}
+
+/// Checks if a key can be used as a shell variable name, ie: letters, digits and `_`, not starting with a digit.
+fn is_shell_identifier(key: &str) -> bool {
+    let mut chars = key.chars();
+    match chars.next() {
+        Some(first) if first.is_ascii_alphabetic() || first == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+/// Flattens the top-level JSON object into `export KEY='value'` lines, to be evaluated by a POSIX shell.
+///
+/// # Arguments
+/// * `secrets` - Decrypted JSON object.
+/// * `invalid_keys` - Handling of the keys that aren't valid shell identifiers.
+///
+/// # Returns
+/// * A `Result<String, String>` containing the export statements or an error message.
+pub fn shell_exports(secrets: &Value, invalid_keys: ShellKeys) -> Result<String, String> {
+    let object = match secrets {
+        Value::Object(object) => object,
+        _ => return Err("Only JSON objects can be written as shell exports".to_string()),
+    };
+    let mut content = String::new();
+    for (key, value) in object {
+        let name = if is_shell_identifier(key) {
+            key.to_string()
+        } else if invalid_keys == ShellKeys::Sanitize && !key.is_empty() {
+            let name: String = key.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect();
+            if name.starts_with(|c: char| c.is_ascii_digit()) { format!("_{}", name) } else { name }
+        } else {
+            log::warn!("Skipping '{}', not a valid shell identifier", key);
+            continue;
+        };
+        // Non-string values are serialized compactly
+        let value = match value {
+            Value::String(text) => text.to_string(),
+            other => other.to_string(),
+        };
+        // Single quotes can't be escaped within single quotes, so each one closes, escapes and reopens the quote
+        content.push_str(&format!("export {}='{}'\n", name, value.replace('\'', "'\\''")));
+    }
+    Ok(content)
+}
+
+/// Writes the content to a file atomically, via a temporary file and rename.
+///
+/// # Arguments
+/// * `content` - Content to write.
+/// * `path` - Destination file path.
+///
+/// # Returns
+/// * A `Result<(), String>` indicating success or an error message.
+pub fn write_atomic(content: &str, path: &Path) -> Result<(), String> {
+    write_atomic_with_mode(content, path, None)
+}
+
+/// Writes the content to a temporary file created with the given permissions, then renames it over the path.
+///
+/// The temporary file has a random name and is created exclusively, so no existing file or symlink is written through.
+///
+/// # Arguments
+/// * `content` - Content to write.
+/// * `path` - Destination file path.
+/// * `mode` - Unix permissions of the file regardless of the umask, ignored on other platforms.
+///
+/// # Returns
+/// * A `Result<(), String>` indicating success or an error message.
+pub fn write_atomic_with_mode(content: &str, path: &Path, mode: Option<u32>) -> Result<(), String> {
+    match crate::atomic::write_atomic(path, mode, |file| file.write_all(content.as_bytes())) {
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("Failed to write {}: {}", path.display(), err)),
+    }
+}
+
+/// Writes the decrypted secrets to a dotenv file, readable by the owner only.
+///
+/// # Arguments
+/// * `secrets` - Decrypted JSON object.
+/// * `path` - Destination file path.
+///
+/// # Returns
+/// * A `Result<(), String>` indicating success or an error message.
+pub fn write_env_file(secrets: &Value, path: &Path) -> Result<(), String> {
+    write_atomic_with_mode(&env_lines(secrets)?, path, Some(0o600))
+}
+
+/// Writes each top-level entry of the decrypted secrets to its own file in a systemd credentials directory.
+///
+/// Files are named after the keys and created with `0600` permissions, so a unit can consume them with
+/// `LoadCredential=`. The directory is created with `0700` permissions if it doesn't exist.
+/// Non-string values are written JSON-encoded.
+///
+/// # Arguments
+/// * `secrets` - Decrypted JSON object.
+/// * `directory` - Credentials directory to write the files into.
+///
+/// # Returns
+/// * A `Result<usize, String>` containing the number of files written, or an error message.
+pub fn write_credentials(secrets: &Value, directory: &Path) -> Result<usize, String> {
+    let map = match secrets {
+        Value::Object(map) => map,
+        _ => return Err("Only JSON objects can be written as systemd credentials".to_string()),
+    };
+    if let Some(key) = map.keys().find(|key| {
+        key.is_empty() || *key == "." || *key == ".." || key.contains('/') || key.contains('\0')
+    }) {
+        return Err(format!("{:?} is not a valid systemd credential name", key));
+    }
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+    if let Err(err) = builder.create(directory) {
+        return Err(format!("Failed to create {}: {}", directory.display(), err));
+    }
+    for (key, value) in map {
+        let content = match value {
+            Value::String(text) => text.to_string(),
+            _ => value.to_string(),
+        };
+        write_atomic_with_mode(&content, &directory.join(key), Some(0o600))?;
+    }
+    Ok(map.len())
+}
+
+/// Writes each top-level entry of the decrypted secrets to the platform credential store.
+///
+/// Each key is stored as an entry under the given service, non-string values are stored JSON-encoded.
+///
+/// # Arguments
+/// * `secrets` - Decrypted JSON object.
+/// * `service` - Service name to store the entries under.
+///
+/// # Returns
+/// * A `Result<usize, String>` containing the number of entries written, or an error message.
+pub fn store_in_keyring(secrets: &Value, service: &str) -> Result<usize, String> {
+    let map = match secrets {
+        Value::Object(map) => map,
+        _ => return Err("Only JSON objects can be stored in the keyring".to_string()),
+    };
+    for (key, value) in map {
+        let password = match value {
+            Value::String(text) => text.to_string(),
+            _ => value.to_string(),
+        };
+        let result = keyring::Entry::new(service, key).and_then(|entry| entry.set_password(&password));
+        if let Err(err) = result {
+            return Err(format!("Failed to store {} in the keyring: {}", key, err));
+        }
+    }
+    Ok(map.len())
+}
+
+/// Renders the decrypted secrets in the requested format.
+///
+/// # Arguments
+/// * `value` - Decrypted JSON value.
+/// * `format` - Output format.
+/// * `pretty` - Indent the JSON output, instead of printing it on a single line.
+///
+/// # Returns
+/// * A `Result<String, String>` containing the rendered content without a trailing newline, or an error message.
+pub fn render(value: &Value, format: OutputFormat, pretty: bool) -> Result<String, String> {
+    let rendered = match format {
+        OutputFormat::Json if pretty => match serde_json::to_string_pretty(value) {
+            Ok(json) => json,
+            Err(err) => return Err(format!("Failed to render as JSON: {}", err)),
+        },
+        OutputFormat::Json => value.to_string(),
+        OutputFormat::Yaml => match serde_yaml::to_string(value) {
+            Ok(yaml) => yaml,
+            Err(err) => return Err(format!("Failed to render as YAML: {}", err)),
+        },
+        OutputFormat::Env => env_lines(value)?,
+        OutputFormat::Shell => shell_exports(value, ShellKeys::default())?,
+        OutputFormat::Ndjson => {
+            let mut buffer = Vec::new();
+            write_ndjson(value, &mut buffer)?;
+            String::from_utf8_lossy(&buffer).to_string()
+        }
+    };
+    Ok(rendered.trim_end_matches('\n').to_string())
+}
+
+/// Writes the decrypted secrets as newline delimited JSON, flushing after each line.
+///
+/// Objects are written as one `{"key": value}` line per entry, and arrays as one line per element.
+/// Stops after the current line when interrupted, see `handle_interrupts`.
+///
+/// # Arguments
+/// * `value` - Decrypted JSON value.
+/// * `writer` - Destination to stream the lines to, eg: stdout.
+///
+/// # Returns
+/// * A `Result<(), String>` indicating success or an error message.
+pub fn write_ndjson<W: Write>(value: &Value, writer: &mut W) -> Result<(), String> {
+    let mut write_line = |line: Value| -> Result<(), String> {
+        if interrupted() {
+            return Err("Interrupted, NDJSON output is incomplete".to_string());
+        }
+        match writeln!(writer, "{}", line).and_then(|_| writer.flush()) {
+            Ok(_) => Ok(()),
+            Err(err) => Err(format!("Failed to write NDJSON output: {}", err)),
+        }
+    };
+    match value {
+        Value::Object(map) => {
+            for (key, entry) in map {
+                write_line(serde_json::json!({ key: entry }))?;
+            }
+            Ok(())
+        }
+        Value::Array(items) => {
+            for item in items {
+                write_line(item.clone())?;
+            }
+            Ok(())
+        }
+        _ => write_line(value.clone()),
+    }
+}
+
+/// Flattens nested objects and arrays into a single object with dotted keys, eg: `{"db": {"password": ""}}`
+/// becomes `{"db.password": ""}`, and array elements are indexed as `a.0.b`.
+///
+/// Keys that already contain a dot can flatten to the same key as a nested one, eg: `{"a.b": 1, "a": {"b": 2}}`,
+/// which is an error instead of keeping either value.
+///
+/// # Arguments
+/// * `value` - Decrypted JSON value.
+///
+/// # Returns
+/// * A `Result<Value, String>` containing an object without any nesting, or the value as is if it is a scalar.
+pub fn flatten(value: &Value) -> Result<Value, String> {
+    fn walk(prefix: &str, value: &Value, flat: &mut serde_json::Map<String, Value>) -> Result<(), String> {
+        let children: Vec<(String, &Value)> = match value {
+            Value::Object(map) if !map.is_empty() => map.iter().map(|(key, child)| (key.to_string(), child)).collect(),
+            Value::Array(items) if !items.is_empty() => items.iter().enumerate().map(|(index, child)| (index.to_string(), child)).collect(),
+            // Scalars and empty containers are leaves
+            _ => {
+                if flat.insert(prefix.to_string(), value.clone()).is_some() {
+                    return Err(format!("More than one value flattens to {:?}, drop --flatten or --select one of them", prefix));
+                }
+                return Ok(());
+            }
+        };
+        for (key, child) in children {
+            let path = if prefix.is_empty() { key } else { format!("{}.{}", prefix, key) };
+            walk(&path, child, flat)?;
+        }
+        Ok(())
+    }
+    let mut flat = serde_json::Map::new();
+    match value {
+        Value::Object(map) if map.is_empty() => Ok(Value::Object(flat)),
+        Value::Array(items) if items.is_empty() => Ok(Value::Object(flat)),
+        Value::Object(_) | Value::Array(_) => {
+            walk("", value, &mut flat)?;
+            Ok(Value::Object(flat))
+        }
+        _ => Ok(value.clone()),
+    }
+}
+
+/// Selects the value at a dotted path, eg: `db.password`, indexing arrays by position as `a.0.b`.
+///
+/// # Arguments
+/// * `value` - Decrypted JSON value.
+/// * `path` - Dotted path to the value.
+///
+/// # Returns
+/// * A `Result<Value, String>` containing the value at the path, or an error message if it doesn't exist.
+pub fn select(value: &Value, path: &str) -> Result<Value, String> {
+    let mut current = value;
+    for segment in path.split('.') {
+        let next = match current {
+            Value::Object(map) => map.get(segment),
+            Value::Array(items) => segment.parse::<usize>().ok().and_then(|index| items.get(index)),
+            _ => None,
+        };
+        current = match next {
+            Some(next) => next,
+            None => return Err(format!("No value found at {}", path)),
+        };
+    }
+    Ok(current.clone())
+}
+
+/// Extracts a single scalar secret to print as is, without quotes or JSON braces.
+///
+/// # Arguments
+/// * `value` - Decrypted JSON value, either a scalar or an object with a single scalar entry.
+///
+/// # Returns
+/// * A `Result<String, String>` containing the bare value, or an error message for non-scalar results.
+pub fn raw_value(value: &Value) -> Result<String, String> {
+    let scalar = match value {
+        Value::Object(map) if map.len() == 1 => map.values().next().unwrap_or(value),
+        _ => value,
+    };
+    match scalar {
+        Value::String(text) => Ok(text.to_string()),
+        Value::Number(_) | Value::Bool(_) => Ok(scalar.to_string()),
+        _ => Err("--raw requires a single scalar value, drop the flag to print the full result".to_string()),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Parses dotenv content the way the env file is loaded, without touching the environment.
+    fn parse_dotenv(content: &str) -> Vec<(String, String)> {
+        dotenvy::from_read_iter(content.as_bytes()).map(|item| item.unwrap()).collect()
+    }
+
+    #[test]
+    fn env_values_with_special_characters_round_trip_through_dotenv() {
+        let values = [
+            "plain",
+            "",
+            "with space",
+            "double \" quote",
+            "single ' quote",
+            "back\\slash",
+            "trailing backslash\\",
+            "first line\nsecond line",
+            "value # not a comment",
+            "#leading hash",
+            "key=value",
+            "dollar $HOME and ${HOME}",
+            "back`tick`",
+            "tab\there",
+        ];
+        for value in values {
+            let content = env_lines(&json!({ "KEY": value })).unwrap();
+            assert_eq!(parse_dotenv(&content), vec![("KEY".to_string(), value.to_string())], "{:?}", content);
+        }
+    }
+
+    #[test]
+    fn env_lines_keep_each_value_to_its_own_variable() {
+        let secrets = json!({ "A": "1\nB=2", "C": "x # y", "D": 3, "E": { "nested": true } });
+        let parsed = parse_dotenv(&env_lines(&secrets).unwrap());
+        assert_eq!(parsed, vec![
+            ("A".to_string(), "1\nB=2".to_string()),
+            ("C".to_string(), "x # y".to_string()),
+            ("D".to_string(), "3".to_string()),
+            ("E".to_string(), "{\"nested\":true}".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn env_lines_skip_keys_that_would_inject_other_variables() {
+        let secrets = json!({ "A\nB": "1", "C=D": "2", "#E": "3", "": "4", "F G": "5", "OK": "6" });
+        assert_eq!(env_lines(&secrets).unwrap(), "OK=6\n");
+    }
+
+    #[test]
+    fn env_file_is_written_readable_by_the_owner_only_and_loads_back() {
+        let directory = std::env::temp_dir().join(format!("vaultapi-env-file-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join(".env");
+        write_env_file(&json!({ "PASSWORD": "p@ss \"word\"\\\n#1" }), &path).unwrap();
+        let parsed: Vec<(String, String)> = dotenvy::from_path_iter(&path).unwrap().map(|item| item.unwrap()).collect();
+        #[cfg(unix)]
+        let mode = {
+            use std::os::unix::fs::PermissionsExt;
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777
+        };
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(parsed, vec![("PASSWORD".to_string(), "p@ss \"word\"\\\n#1".to_string())]);
+        #[cfg(unix)]
+        assert_eq!(mode, 0o600);
+    }
+
+    /// Evaluates the export statements in `sh` and returns the value it then holds for `KEY`.
+    #[cfg(unix)]
+    fn eval_in_shell(exports: &str) -> String {
+        let output = std::process::Command::new("sh")
+            .args(["-c", "eval \"$0\"; printf %s \"$KEY\"", exports])
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    #[test]
+    fn shell_exports_quote_each_value_as_a_single_word() {
+        let exports = shell_exports(&json!({ "KEY": "it's a \"test\"" }), ShellKeys::Skip).unwrap();
+        assert_eq!(exports, "export KEY='it'\\''s a \"test\"'\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn shell_exports_keep_hostile_values_inert() {
+        let marker = std::env::temp_dir().join(format!("vaultapi-shell-marker-{}", std::process::id()));
+        let injection = format!("'; touch {} #", marker.display());
+        let values = [
+            injection.as_str(),
+            "'; rm -rf ~ #",
+            "first line\nsecond line\n",
+            "$(touch /nonexistent/vaultapi) `id` $HOME ${HOME}",
+            "back\\slash \\' and \\n",
+            "'",
+            "''",
+            "",
+            "; exit 1",
+        ];
+        for value in values {
+            let exports = shell_exports(&json!({ "KEY": value }), ShellKeys::Skip).unwrap();
+            assert_eq!(eval_in_shell(&exports), value, "{:?}", exports);
+        }
+        assert!(!marker.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn shell_exports_serialize_non_string_values() {
+        let exports = shell_exports(&json!({ "KEY": { "quote": "'" } }), ShellKeys::Skip).unwrap();
+        assert_eq!(eval_in_shell(&exports), "{\"quote\":\"'\"}");
+    }
+
+    #[test]
+    fn shell_exports_skip_or_sanitize_invalid_identifiers() {
+        let secrets = json!({ "1st": "a", "my-key": "b", "$(id)": "c", "OK_1": "d" });
+        assert_eq!(shell_exports(&secrets, ShellKeys::Skip).unwrap(), "export OK_1='d'\n");
+        let sanitized = shell_exports(&secrets, ShellKeys::Sanitize).unwrap();
+        assert_eq!(sanitized, "export __id_='c'\nexport _1st='a'\nexport OK_1='d'\nexport my_key='b'\n");
+    }
+
+    #[test]
+    fn flatten_indexes_nested_objects_and_arrays() {
+        let secrets = json!({ "db": { "hosts": [{ "name": "a" }, { "name": "b" }], "port": 5432 }, "tags": [["x"], []] });
+        assert_eq!(flatten(&secrets).unwrap(), json!({
+            "db.hosts.0.name": "a",
+            "db.hosts.1.name": "b",
+            "db.port": 5432,
+            "tags.0.0": "x",
+            "tags.1": [],
+        }));
+        assert_eq!(flatten(&json!([1, { "a": null }])).unwrap(), json!({ "0": 1, "1.a": null }));
+        assert_eq!(flatten(&json!("scalar")).unwrap(), json!("scalar"));
+        assert_eq!(flatten(&json!({})).unwrap(), json!({}));
+        assert_eq!(flatten(&json!([])).unwrap(), json!({}));
+    }
+
+    #[test]
+    fn flatten_rejects_keys_that_collide() {
+        let err = flatten(&json!({ "a.b": 1, "a": { "b": 2 } })).unwrap_err();
+        assert!(err.contains("\"a.b\""), "{}", err);
+        assert!(flatten(&json!({ "list.0": 1, "list": [2] })).is_err());
+    }
+
+    #[test]
+    fn select_follows_object_keys_and_array_indices() {
+        let secrets = json!({ "db": { "hosts": [{ "name": "a" }, { "name": "b" }] }, "a.b": 1 });
+        assert_eq!(select(&secrets, "db.hosts.1.name").unwrap(), json!("b"));
+        assert_eq!(select(&secrets, "db.hosts").unwrap(), json!([{ "name": "a" }, { "name": "b" }]));
+    }
+
+    #[test]
+    fn select_reports_a_missing_path() {
+        let secrets = json!({ "db": { "hosts": [{ "name": "a" }], "port": 5432 }, "a.b": 1 });
+        // Dots always separate the segments, so a key containing one can't be selected
+        for path in ["missing", "db.user", "db.hosts.1", "db.hosts.first", "db.port.value", "db.hosts.-1", "", "a.b"] {
+            assert_eq!(select(&secrets, path).unwrap_err(), format!("No value found at {}", path));
+        }
+    }
+}
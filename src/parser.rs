@@ -1,166 +1,1200 @@
-use reqwest::Url;
+use crate::config::{
+    ACCEPT, PAGE_SIZE, PARALLEL_LIMIT, PATH_GET_SECRET, PATH_GET_SECRETS, PATH_GET_TABLE, PATH_PUT_SECRET, PATH_DELETE_SECRET, PATH_LIST_TABLES, PATH_LIST_KEYS, PATH_WATCH, POOL_IDLE_TIMEOUT, REQUEST_JITTER_MS, RETRIES, RETRY_BACKOFF_MS, TIMEOUT,
+    TRANSIT_DERIVATION_SEPARATOR, TRANSIT_HMAC_LENGTH, TRANSIT_KEY_LENGTH, TRANSIT_TAG_LENGTH, TRANSIT_TIME_BUCKET, TRANSIT_TOLERANCE,
+};
 use crate::constant;
+use crate::decipher::{Base64Variant, CipherSuite, DerivationOrder, HashAlgorithm, HmacPlacement, PayloadCompression};
+use crate::logger::LogFormat;
+use crate::output::{OutputFormat, ShellKeys};
+use clap::builder::BoolishValueParser;
+use clap::{ArgAction, CommandFactory, FromArgMatches, Parser, Subcommand};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io::Read;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 
-const TRANSIT_KEY_LENGTH: usize = 32;
-const TRANSIT_TIME_BUCKET: u64 = 60;
+pub use crate::config::{parse_vault_server, Config};
 
-pub struct Config {
-    pub vault_server: Url,
-    pub apikey: String,
-    pub transit_key_length: usize,
-    pub transit_time_bucket: u64,
+/// Set by `--no-env`, to resolve the settings from the arguments and the config file alone.
+static IGNORE_ENV: AtomicBool = AtomicBool::new(false);
 
-    pub cipher: String,
-    pub table_name: String,
-    pub get_secret: String,
-    pub get_secrets: String,
-    pub get_table: String,
+/// Reads an environment variable, unless `--no-env` is set.
+///
+/// # Arguments
+/// * `name` - Name of the environment variable.
+///
+/// # Returns
+/// * An `Option<String>` with the value, or `None` when unset, not valid unicode or ignored.
+fn env_var(name: &str) -> Option<String> {
+    if IGNORE_ENV.load(Ordering::Relaxed) {
+        return None;
+    }
+    std::env::var(name).ok()
 }
 
 
-fn get_env(key: &str, default: &str) -> String {
-    match std::env::var(key) {
+/// Settings that can be loaded from a TOML config file with `--config`.
+///
+/// Unknown keys are rejected, so typos are reported instead of being silently ignored.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileConfig {
+    vault_server: Option<String>,
+    fallback_server: Option<String>,
+    apikey: Option<String>,
+    apikey_previous: Option<String>,
+    transit_key_length: Option<usize>,
+    transit_time_bucket: Option<u64>,
+    transit_tolerance: Option<u64>,
+    transit_tag_length: Option<usize>,
+    transit_epoch_override: Option<u64>,
+    cipher_suite: Option<String>,
+    hash_algorithm: Option<String>,
+    base64_variant: Option<String>,
+    derivation_order: Option<String>,
+    derivation_separator: Option<String>,
+    transit_constant_time: Option<bool>,
+    transit_in_place: Option<bool>,
+    transit_verify_hmac: Option<bool>,
+    transit_hmac_length: Option<usize>,
+    transit_hmac_placement: Option<String>,
+    transit_decompress: Option<String>,
+    transit_aad: Option<bool>,
+    timeout: Option<u64>,
+    http2_prior_knowledge: Option<bool>,
+    tcp_keepalive: Option<u64>,
+    pool_idle_timeout: Option<u64>,
+    retries: Option<u32>,
+    retry_backoff_ms: Option<u64>,
+    request_jitter_ms: Option<u64>,
+    ca_cert: Option<String>,
+    insecure: Option<bool>,
+    proxy: Option<String>,
+    namespace: Option<String>,
+    path_get_secret: Option<String>,
+    path_get_secrets: Option<String>,
+    path_get_table: Option<String>,
+    path_put_secret: Option<String>,
+    path_delete_secret: Option<String>,
+    path_list_tables: Option<String>,
+    path_list_keys: Option<String>,
+    path_watch: Option<String>,
+    discover: Option<String>,
+    page_size: Option<u32>,
+    parallel_limit: Option<usize>,
+    accept: Option<String>,
+    compression: Option<bool>,
+    user_agent: Option<String>,
+    repeat_keys: Option<bool>,
+    percent_encode: Option<bool>,
+    cache_ttl: Option<u64>,
+    apikeys: Option<HashMap<String, String>>,
+}
+
+
+/// Expands `${NAME}` and `${NAME:-default}` references to environment variables in a config value.
+///
+/// `$$` is a literal `$`, and a `$` not followed by `{` is kept as is. With `--no-env`, every variable is unset.
+///
+/// # Arguments
+/// * `value` - Raw string value from the config file.
+///
+/// # Returns
+/// * A `Result<String, String>` containing the expanded value, or an error for an undefined variable without a default.
+fn interpolate(value: &str) -> Result<String, String> {
+    let mut expanded = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if let Some(after) = rest.strip_prefix("$$") {
+            expanded.push('$');
+            rest = after;
+            continue;
+        }
+        let Some(reference) = rest.strip_prefix("${") else {
+            expanded.push('$');
+            rest = &rest[1..];
+            continue;
+        };
+        let Some(end) = reference.find('}') else {
+            return Err(format!("Unterminated variable reference in {:?}", value));
+        };
+        let (name, default) = match reference[..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&reference[..end], None),
+        };
+        // The default also applies to a variable set to an empty string, like in the shell
+        match (env_var(name).filter(|value| !value.is_empty()), default) {
+            (Some(value), _) => expanded.push_str(&value),
+            (None, Some(default)) => expanded.push_str(default),
+            (None, None) => return Err(format!("Environment variable {} is not set", name)),
+        }
+        rest = &reference[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Expands the environment variable references in every string of a parsed config file, including nested tables.
+fn interpolate_values(value: &mut toml::Value) -> Result<(), String> {
+    match value {
+        toml::Value::String(text) => *text = interpolate(text)?,
+        toml::Value::Array(items) => {
+            for item in items {
+                interpolate_values(item)?;
+            }
+        }
+        toml::Value::Table(table) => {
+            for (_, item) in table.iter_mut() {
+                interpolate_values(item)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn load_config_file(path: &str) -> FileConfig {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(err) => {
+            eprintln!("Failed to read config file {}: {}", path, err);
+            std::process::exit(2)
+        }
+    };
+    let mut value = match toml::from_str::<toml::Value>(&content) {
         Ok(value) => value,
+        Err(err) => {
+            eprintln!("Invalid config file {}: {}", path, err.message());
+            std::process::exit(2)
+        }
+    };
+    if let Err(err) = interpolate_values(&mut value) {
+        eprintln!("Invalid config file {}: {}", path, err);
+        std::process::exit(2)
+    }
+    match value.try_into::<FileConfig>() {
+        Ok(file) => file,
+        Err(err) => {
+            eprintln!("Invalid config file {}: {}", path, err.message());
+            std::process::exit(2)
+        }
+    }
+}
+
+
+/// Resolves a setting with the precedence: command line > environment variable > config file.
+fn resolve(cli: String, env_key: &str, file_value: Option<String>) -> String {
+    if !cli.is_empty() {
+        return cli;
+    }
+    if let Some(value) = env_var(env_key) {
+        return value;
+    }
+    file_value.unwrap_or_default()
+}
+
+
+/// Resolves a boolean setting with the same precedence as `resolve`, so `--flag=false` turns off a setting enabled by
+/// the environment or the config file. An empty environment variable is treated as unset.
+fn resolve_bool(cli: Option<bool>, env_key: &str, file_value: Option<bool>, default: bool) -> bool {
+    if let Some(value) = cli {
+        return value;
+    }
+    match env_var(env_key) {
+        Some(value) if !value.is_empty() => parse_bool(&value),
+        _ => file_value.unwrap_or(default),
+    }
+}
+
+
+/// Resolves a numeric setting with the same precedence as `resolve`, exiting when the value is not a number.
+fn resolve_number<T: FromStr + ToString>(
+    name: &str, cli: String, env_key: &str, file_value: Option<T>, default: T
+) -> T {
+    let value = resolve(cli, env_key, file_value.map(|v| v.to_string()));
+    parse_number(name, &or_default(value, default))
+}
+
+
+/// Resolves one of the choices of `T` with the same precedence as `resolve`, exiting when the value is not one of them.
+fn resolve_choice<T: FromStr<Err = String> + Default>(cli: String, env_key: &str, file_value: Option<String>) -> T {
+    let value = resolve(cli, env_key, file_value);
+    if value.is_empty() {
+        return T::default();
+    }
+    parse_choice(&value)
+}
+
+
+/// Reads a value such as the apikey or the cipher text from stdin or a file, trimming the surrounding whitespace.
+fn read_input(name: &str, from_stdin: bool, path: &str) -> String {
+    let source = if from_stdin { "stdin".to_string() } else { path.to_string() };
+    let mut content = String::new();
+    let result = if from_stdin {
+        std::io::stdin().read_to_string(&mut content).map(|_| ())
+    } else {
+        std::fs::read_to_string(path).map(|value| content = value)
+    };
+    if let Err(err) = result {
+        eprintln!("Failed to read {} from {}: {}", name, source, err);
+        std::process::exit(2)
+    }
+    let value = content.trim().to_string();
+    if value.is_empty() {
+        eprintln!("No {} found in {}", name, source);
+        std::process::exit(2)
+    }
+    value
+}
+
+
+fn or_default<T: ToString>(value: String, default: T) -> String {
+    if value.is_empty() {
+        return default.to_string();
+    }
+    value
+}
+
+
+fn required(key: &str, value: String) -> String {
+    if value.is_empty() && IGNORE_ENV.load(Ordering::Relaxed) {
+        eprintln!("{:} not set in the arguments or the config file, environment variables are ignored with --no-env", key);
+        std::process::exit(2)
+    }
+    if value.is_empty() {
+        eprintln!("{:} environment variable not set", key);
+        std::process::exit(2)
+    }
+    value
+}
+
+
+fn parse_choice<T: FromStr<Err = String>>(value: &str) -> T {
+    match value.parse::<T>() {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(2)
+        }
+    }
+}
+
+
+/// Parses a boolean setting, where `1`, `true`, `yes` and `on` in any case are true and anything else is false.
+fn parse_bool(value: &str) -> bool {
+    matches!(value.to_lowercase().as_str(), "1" | "true" | "yes" | "on")
+}
+
+
+fn parse_number<T: std::str::FromStr>(name: &str, value: &str) -> T {
+    match value.parse::<T>() {
+        Ok(parsed) => parsed,
         Err(_) => {
-            if !default.is_empty() {
-                return default.to_string();
-            }
-            println!("{:} environment variable not set", key);
-            std::process::exit(1)
+            eprintln!("{} must be a positive integer, received: {}", name, value);
+            std::process::exit(2)
         }
     }
 }
 
 
+/// Command line interface, with one subcommand per operation.
+#[derive(Parser)]
+#[command(about = "Client application for VaultAPI server", disable_version_flag = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+    /// Get the package version.
+    #[arg(short = 'v', short_alias = 'V', long, action = ArgAction::Version)]
+    version: Option<bool>,
+    /// Custom filename to load the environment variables. Defaults to '.env'
+    #[arg(long = "env-file", alias = "env_file", global = true)]
+    env_file: Option<String>,
+    /// Ignore the environment variables and the env file, taking the settings from the arguments and --config only.
+    #[arg(long, global = true, conflicts_with = "env_file")]
+    no_env: bool,
+    /// TOML file to load the settings from, overridden by env vars and arguments.
+    #[arg(long = "config", global = true)]
+    config_file: Option<String>,
+    /// APIkey to authenticate the server, visible to other users in the process list.
+    #[arg(long, global = true)]
+    apikey: Option<String>,
+    /// File to read the apikey from, takes precedence over --apikey and the APIKEY env var.
+    #[arg(long, global = true)]
+    apikey_file: Option<String>,
+    /// APIkey in use before a rotation, to decrypt payloads that were encrypted with it.
+    #[arg(long, global = true)]
+    apikey_previous: Option<String>,
+    /// Read the apikey from stdin, takes precedence over --apikey-file.
+    #[arg(long, global = true)]
+    apikey_stdin: bool,
+    /// Print the request that would be sent, with header values redacted, without sending it.
+    #[arg(long, global = true)]
+    dry_run: bool,
+    /// Check that the secrets can be retrieved and decrypted, printing only OK or the failure category.
+    #[arg(long, global = true)]
+    verify: bool,
+    /// Re-fetch every given number of seconds, rewriting the output only when the secrets change.
+    #[arg(long, global = true)]
+    watch: Option<String>,
+    /// Seconds to serve a retrieved secret from memory for repeated requests, 0 to disable.
+    #[arg(long, global = true)]
+    cache_ttl: Option<String>,
+    /// UNIX timestamp in seconds to derive the transit epoch from, instead of the system clock.
+    #[arg(long = "epoch", global = true)]
+    epoch_override: Option<String>,
+    /// Request timeout in seconds. Defaults to 30
+    #[arg(long, global = true)]
+    timeout: Option<String>,
+    /// Send requests over HTTP/2 without negotiating it, for servers known to support it.
+    #[arg(long, global = true, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL", value_parser = BoolishValueParser::new())]
+    http2_prior_knowledge: Option<bool>,
+    /// Interval of the TCP keep-alive probes in seconds, 0 to disable. Defaults to 0
+    #[arg(long, global = true)]
+    tcp_keepalive: Option<String>,
+    /// Seconds to keep idle connections open for reuse. Defaults to 90
+    #[arg(long, global = true)]
+    pool_idle_timeout: Option<String>,
+    /// Number of retries on transient failures. Defaults to 3
+    #[arg(long, global = true)]
+    retries: Option<String>,
+    /// Base delay for the exponential backoff in milliseconds. Defaults to 500
+    #[arg(long, global = true)]
+    retry_backoff_ms: Option<String>,
+    /// Maximum random delay before each request in milliseconds, 0 to disable. Defaults to 0
+    #[arg(long, global = true)]
+    request_jitter_ms: Option<String>,
+    /// Output format, one of json, yaml, env, ndjson, shell. Defaults to json
+    #[arg(long, global = true)]
+    format: Option<OutputFormat>,
+    /// Keys that aren't valid shell identifiers with --format shell, skip or sanitize. Defaults to skip
+    #[arg(long, global = true)]
+    shell_keys: Option<ShellKeys>,
+    /// Print a single scalar secret without quotes or JSON braces.
+    #[arg(long, global = true)]
+    raw: bool,
+    /// Indent the JSON output for humans, with --format json.
+    #[arg(long, global = true, overrides_with = "compact")]
+    pretty: bool,
+    /// Print the JSON output on a single line, the default for machine consumption.
+    #[arg(long, global = true, overrides_with = "pretty")]
+    compact: bool,
+    /// Flatten nested objects and arrays into dotted keys, eg: db.password
+    #[arg(long, global = true)]
+    flatten: bool,
+    /// Return only the value at a dotted path, eg: db.password or hosts.0.name
+    #[arg(long, global = true)]
+    select: Option<String>,
+    /// Write the decrypted secrets to a dotenv file instead of printing them.
+    #[arg(long, global = true)]
+    write_env: Option<String>,
+    /// Write the decrypted secrets to this file atomically instead of printing them, in the --format.
+    #[arg(long, global = true)]
+    output: Option<String>,
+    /// Permissions of the --output file in octal. Defaults to 0600
+    #[arg(long, global = true, value_parser = parse_mode)]
+    output_mode: Option<u32>,
+    /// Store each decrypted secret in the OS keyring under this service name.
+    #[arg(long, global = true)]
+    keyring_store: Option<String>,
+    /// Write each decrypted secret to its own 0600 file in this directory, defaults to $CREDENTIALS_DIRECTORY.
+    #[arg(long, global = true, num_args = 0..=1, require_equals = true, default_missing_value = "", value_name = "DIRECTORY")]
+    systemd_creds: Option<String>,
+    /// PEM file with a CA certificate to trust for the Vault server.
+    #[arg(long, global = true)]
+    ca_cert: Option<String>,
+    /// Namespace of the tables in a multi-tenant server, sent as X-Vault-Namespace.
+    #[arg(long, global = true)]
+    namespace: Option<String>,
+    /// Extra header to send as KEY=VALUE, can be repeated.
+    #[arg(long = "header", global = true, value_parser = parse_header)]
+    headers: Vec<(String, String)>,
+    /// Media types accepted for the response, eg: application/msgpack. Defaults to application/json
+    #[arg(long, global = true)]
+    accept: Option<String>,
+    /// Secondary VaultAPI server to retry against when the primary fails with a connection error or 5xx.
+    #[arg(long, global = true)]
+    fallback_server: Option<String>,
+    /// URL of a discovery document listing the paths of the server routes, fetched before the first request.
+    #[arg(long, global = true)]
+    discover: Option<String>,
+    /// HTTP or SOCKS proxy URL, credentials can be embedded as user:password@host.
+    #[arg(long, global = true)]
+    proxy: Option<String>,
+    /// Report all decryption failures alike, in comparable time.
+    #[arg(long, global = true, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL", value_parser = BoolishValueParser::new())]
+    constant_time: Option<bool>,
+    /// Decrypt within the decoded buffer instead of a copy, for memory-constrained hosts.
+    #[arg(long, global = true, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL", value_parser = BoolishValueParser::new())]
+    in_place: Option<bool>,
+    /// Verify the HMAC-SHA256 attached to the ciphertext before decrypting it.
+    #[arg(long, global = true, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL", value_parser = BoolishValueParser::new())]
+    verify_hmac: Option<bool>,
+    /// Compression of the plaintext to undo after decrypting it: gzip, zstd, none or auto. Defaults to none
+    #[arg(long, global = true)]
+    decompress: Option<String>,
+    /// Bind the ciphertext to the table name as additional authenticated data.
+    #[arg(long, global = true, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL", value_parser = BoolishValueParser::new())]
+    aad: Option<bool>,
+    /// User-Agent header sent with every request. Defaults to vaultapi-client/<version>
+    #[arg(long, global = true)]
+    user_agent: Option<String>,
+    /// X-Request-ID header sent with every request, to trace it across services. Defaults to a random UUID per request
+    #[arg(long, global = true)]
+    request_id: Option<String>,
+    /// Accept gzip and deflate response compression, =false for servers that mishandle the encoding. Defaults to true
+    #[arg(long, global = true, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL", value_parser = BoolishValueParser::new())]
+    compression: Option<bool>,
+    /// Disable gzip and deflate response compression, same as --compression=false.
+    #[arg(long, global = true, conflicts_with = "compression")]
+    no_compression: bool,
+    /// Percent-encode the table and key names in the query, eg: a space as %20 instead of +.
+    #[arg(long, global = true, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL", value_parser = BoolishValueParser::new())]
+    percent_encode: Option<bool>,
+    /// Skip TLS certificate verification, for local testing only.
+    #[arg(long, global = true, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL", value_parser = BoolishValueParser::new())]
+    insecure: Option<bool>,
+    /// Enable debug level logging.
+    #[arg(long, global = true)]
+    debug: bool,
+    /// Suppress everything but errors on stderr, so stdout only carries the secrets, eg: for `$(...)`.
+    #[arg(long, global = true, conflicts_with = "debug")]
+    quiet: bool,
+    /// Log timestamps in UTC instead of local time.
+    #[arg(long, global = true)]
+    utc: bool,
+    /// Log format, one of text, json. Defaults to text
+    #[arg(long, global = true)]
+    log_format: Option<LogFormat>,
+    /// Include decrypted secret values in the debug logs.
+    #[arg(long, global = true)]
+    log_secrets: bool,
+}
+
+/// Operations supported by the command line interface.
+#[derive(Subcommand)]
+enum Command {
+    /// Get the value of a particular secret key.
+    GetSecret {
+        /// Name of the table to retrieve the secret from.
+        #[arg(long)]
+        table: String,
+        /// Name of the secret.
+        key: String,
+    },
+    /// Get the values of multiple keys using a comma separated list.
+    GetSecrets {
+        /// Name of the table to retrieve the secrets from.
+        #[arg(long)]
+        table: String,
+        /// Comma separated list of secret keys.
+        keys: String,
+        /// Retrieve each of the keys with its own request, concurrently.
+        #[arg(long)]
+        parallel: bool,
+        /// Maximum number of requests in flight with --parallel.
+        #[arg(long)]
+        parallel_limit: Option<String>,
+        /// Fail listing the keys the server didn't return, instead of omitting them.
+        #[arg(long)]
+        require_all: bool,
+    },
+    /// Get all the secrets stored in a table.
+    GetTable {
+        /// Name of the table.
+        table: String,
+        /// Number of rows to request per page, 0 to disable pagination.
+        #[arg(long)]
+        page_size: Option<String>,
+    },
+    /// Encrypt a value and store it as a secret on the server.
+    PutSecret {
+        /// Name of the table to store the secret in.
+        #[arg(long)]
+        table: String,
+        /// Name of the secret.
+        key: String,
+        /// Value of the secret, stored as a string unless it is valid JSON.
+        value: String,
+    },
+    /// Delete a secret from the server.
+    DeleteSecret {
+        /// Name of the table to delete the secret from.
+        #[arg(long)]
+        table: String,
+        /// Name of the secret.
+        key: String,
+        /// Confirm the deletion, which is refused otherwise.
+        #[arg(long)]
+        yes: bool,
+    },
+    /// List the names of the tables on the server.
+    ListTables,
+    /// List the names of the secrets in a table.
+    ListKeys {
+        /// Name of the table.
+        table: String,
+    },
+    /// Stream the updates of a table pushed by the server as server-sent events, reconnecting on disconnect.
+    WatchRemote {
+        /// Name of the table to watch.
+        #[arg(long)]
+        table: String,
+    },
+    /// Decrypt a cipher text to a JSON value, without contacting the server.
+    Decrypt {
+        /// Cipher text to decrypt.
+        #[arg(required_unless_present_any = ["cipher_stdin", "cipher_file"])]
+        cipher: Option<String>,
+        /// Read the cipher text from stdin.
+        #[arg(long, conflicts_with_all = ["cipher", "cipher_file"])]
+        cipher_stdin: bool,
+        /// File to read the cipher text from.
+        #[arg(long, conflicts_with = "cipher")]
+        cipher_file: Option<String>,
+        /// Name of the table the cipher text is bound to, when using --aad.
+        #[arg(long)]
+        table: Option<String>,
+    },
+    /// Decrypt a file of cipher texts, one per line, to NDJSON without contacting the server.
+    DecryptBatch {
+        /// File with one base64 cipher text per line.
+        #[arg(long)]
+        input: String,
+        /// Name of the table the cipher texts are bound to, when using --aad.
+        #[arg(long)]
+        table: Option<String>,
+    },
+    /// Check that the server is reachable, and print its status and version.
+    Health,
+}
+
+
+/// Parses an extra header in the format `KEY=VALUE`.
+fn parse_header(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((key, header)) if !key.trim().is_empty() => Ok((key.trim().to_string(), header.to_string())),
+        _ => Err(format!("must be in the format KEY=VALUE, received: {}", value)),
+    }
+}
+
+
+/// Parses file permissions in octal, eg: `600`, `0600` or `0o600`.
+fn parse_mode(value: &str) -> Result<u32, String> {
+    let digits = value.strip_prefix("0o").unwrap_or(value);
+    match u32::from_str_radix(digits, 8) {
+        Ok(mode) if mode <= 0o777 => Ok(mode),
+        _ => Err(format!("must be octal permissions between 000 and 777, received: {}", value)),
+    }
+}
+
+/// Operation of the command line, besides sending the request of the subcommand and writing out its result once.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mode {
+    /// Sends the request of the subcommand, or decrypts the cipher text, and writes out the result.
+    Once,
+    /// Prints the request that would be sent, with `--dry-run`.
+    DryRun,
+    /// Prints only whether the secrets could be retrieved and decrypted, with `--verify`.
+    Verify,
+    /// Re-fetches the secrets at the interval, writing them out when they change, with `--watch`.
+    Watch(Duration),
+    /// Writes out each update pushed by the server, with `watch-remote`.
+    WatchRemote,
+    /// Decrypts one cipher text per line of the file, with `decrypt-batch`.
+    DecryptBatch(String),
+    /// Prints the health of the server, with `health`.
+    Health,
+}
+
+/// Operation of the command line, destinations and formats of the decrypted secrets, and the log settings.
+pub struct Output {
+    pub mode: Mode,
+    pub select: String,
+    pub flatten: bool,
+    pub write_env: String,
+    pub keyring_store: String,
+    pub systemd_creds: String,
+    pub output: String,
+    pub output_mode: u32,
+    pub raw: bool,
+    pub pretty: bool,
+    pub format: OutputFormat,
+    pub shell_keys: ShellKeys,
+    pub log_format: LogFormat,
+    pub debug: bool,
+    pub quiet: bool,
+    pub utc: bool,
+    pub log_secrets: bool,
+}
+
 /// Parses and returns the command-line arguments and environment variables.
 ///
 /// # Returns
-/// A String notion of the argument, `env_file` if present.
-pub fn arguments(metadata: &constant::MetaData) -> Config {
-    let args: Vec<String> = std::env::args().collect();
+/// A tuple of the `Config` object with the settings resolved from the arguments, env vars, and the config file,
+/// and the `Output` settings of the command line.
+pub fn arguments(metadata: &constant::MetaData) -> (Config, Output) {
+    let command = Cli::command()
+        .name(metadata.pkg_name.to_string())
+        .version(metadata.pkg_version.to_string());
+    let cli = match Cli::from_arg_matches(&command.get_matches()) {
+        Ok(cli) => cli,
+        Err(err) => err.exit(),
+    };
 
-    let mut version = false;
-    let mut env_file = String::new();
-    let mut cipher = String::new();
     let mut table_name = String::new();
     let mut get_secret = String::new();
     let mut get_secrets = String::new();
     let mut get_table = String::new();
-
-    // Loop through the command-line arguments and parse them.
-    let mut i = 1; // Start from the second argument (args[0] is the program name).
-    while i < args.len() {
-        match args[i].as_str() {
-            "-h" | "--help" => {
-                let helper = "VaultAPI-Client takes the arguments, --env_file and --version/-v\n\n\
-                --env_file: Custom filename to load the environment variables. Defaults to '.env'\n\
-                --cipher: Cipher text to decrypt\n\
-                --version: Get the package version.\n".to_string();
-                println!("Usage: {} [OPTIONS]\n\n{}", args[0], helper);
-                std::process::exit(0)
-            }
-            "-V" | "-v" | "--version" => {
-                version = true;
-            }
-            "--env_file" => {
-                i += 1; // Move to the next argument.
-                if i < args.len() {
-                    env_file = args[i].clone();
-                } else {
-                    println!("--env_file requires a value.");
-                    std::process::exit(1)
-                }
-            }
-            "--cipher" => {
-                i += 1; // Move to the next argument.
-                if i < args.len() {
-                    cipher = args[i].clone();
-                } else {
-                    println!("--cipher requires a value.");
-                    std::process::exit(1)
-                }
-            }
-            "--table" => {
-                i += 1; // Move to the next argument.
-                if i < args.len() {
-                    table_name = args[i].clone();
-                } else {
-                    println!("--get-secret requires a value.");
-                    std::process::exit(1)
-                }
-            }
-            "--get-secret" => {
-                i += 1; // Move to the next argument.
-                if i < args.len() {
-                    get_secret = args[i].clone();
-                } else {
-                    println!("--get-secret requires a value.");
-                    std::process::exit(1)
-                }
-            }
-            "--get-secrets" => {
-                i += 1; // Move to the next argument.
-                if i < args.len() {
-                    get_secrets = args[i].clone();
-                } else {
-                    println!("--get-secrets requires a value.");
-                    std::process::exit(1)
-                }
+    let mut put_secret = String::new();
+    let mut put_value = String::new();
+    let mut delete_secret = String::new();
+    let mut list_tables = false;
+    let mut list_keys = false;
+    let mut watch_remote = false;
+    let mut cipher = String::new();
+    let mut decrypt_batch = String::new();
+    let mut parallel = false;
+    let mut parallel_limit = String::new();
+    let mut require_all = false;
+    let mut page_size = String::new();
+    let mut health = false;
+    match cli.command {
+        Command::GetSecret { table, key } => {
+            table_name = table;
+            get_secret = key;
+        }
+        Command::GetSecrets { table, keys, parallel: concurrent, parallel_limit: limit, require_all: all } => {
+            table_name = table;
+            get_secrets = keys;
+            parallel = concurrent;
+            parallel_limit = limit.unwrap_or_default();
+            require_all = all;
+        }
+        Command::GetTable { table, page_size: size } => {
+            get_table = table;
+            page_size = size.unwrap_or_default();
+        }
+        Command::PutSecret { table, key, value } => {
+            table_name = table;
+            put_secret = key;
+            put_value = value;
+        }
+        Command::DeleteSecret { table, key, yes } => {
+            if !yes && !cli.dry_run {
+                eprintln!("Refusing to delete '{}' from '{}' without --yes", key, table);
+                std::process::exit(2)
             }
-            "--get-table" => {
-                i += 1; // Move to the next argument.
-                if i < args.len() {
-                    get_table = args[i].clone();
-                } else {
-                    println!("--get-table requires a value.");
-                    std::process::exit(1)
-                }
+            table_name = table;
+            delete_secret = key;
+        }
+        Command::ListTables => {
+            list_tables = true;
+        }
+        Command::ListKeys { table } => {
+            table_name = table;
+            list_keys = true;
+        }
+        Command::WatchRemote { table } => {
+            if cli.dry_run || cli.verify || cli.watch.is_some() {
+                eprintln!("watch-remote cannot be used with --dry-run, --verify or --watch");
+                std::process::exit(2)
             }
-            _ => {
-                println!("Unknown argument: {}", args[i]);
-                std::process::exit(1)
+            table_name = table;
+            watch_remote = true;
+        }
+        Command::Decrypt { cipher: text, cipher_stdin, cipher_file, table } => {
+            if cipher_stdin && cli.apikey_stdin {
+                eprintln!("--cipher-stdin and --apikey-stdin cannot be used together");
+                std::process::exit(2)
             }
+            cipher = match (text, cipher_file) {
+                (Some(text), _) => text,
+                (None, Some(path)) => read_input("cipher text", false, &path),
+                (None, None) => read_input("cipher text", true, ""),
+            };
+            table_name = table.unwrap_or_default();
+        }
+        Command::DecryptBatch { input, table } => {
+            decrypt_batch = input;
+            table_name = table.unwrap_or_default();
+        }
+        Command::Health => {
+            health = true;
         }
-        i += 1;
-    }
-    if version {
-        println!("{} {}", &metadata.pkg_name, &metadata.pkg_version);
-        std::process::exit(0)
     }
+    let mut env_file = cli.env_file.unwrap_or_default();
+    let config_file = cli.config_file.unwrap_or_default();
+    let no_env = cli.no_env;
+    IGNORE_ENV.store(no_env, Ordering::Relaxed);
+    let mut apikey = cli.apikey.unwrap_or_default();
+    let apikey_file = cli.apikey_file.unwrap_or_default();
+    let apikey_stdin = cli.apikey_stdin;
+    let apikey_previous = cli.apikey_previous.unwrap_or_default();
+    let timeout = cli.timeout.unwrap_or_default();
+    let http2_prior_knowledge = cli.http2_prior_knowledge;
+    let tcp_keepalive = cli.tcp_keepalive.unwrap_or_default();
+    let pool_idle_timeout = cli.pool_idle_timeout.unwrap_or_default();
+    let retries = cli.retries.unwrap_or_default();
+    let retry_backoff_ms = cli.retry_backoff_ms.unwrap_or_default();
+    let request_jitter_ms = cli.request_jitter_ms.unwrap_or_default();
+    let cache_ttl = cli.cache_ttl.unwrap_or_default();
+    let watch = cli.watch.unwrap_or_default();
+    let epoch_override = cli.epoch_override.unwrap_or_default();
+    let verify = cli.verify;
+    let select = cli.select.unwrap_or_default();
+    let accept = cli.accept.unwrap_or_default();
+    let flatten = cli.flatten;
+    let write_env = cli.write_env.unwrap_or_default();
+    let output = cli.output.unwrap_or_default();
+    let output_mode = cli.output_mode.unwrap_or(0o600);
+    let keyring_store = cli.keyring_store.unwrap_or_default();
+    let systemd_creds = match cli.systemd_creds {
+        Some(directory) if directory.is_empty() => match env_var("CREDENTIALS_DIRECTORY") {
+            Some(directory) if !directory.is_empty() => directory,
+            _ => {
+                eprintln!("--systemd-creds requires a directory when CREDENTIALS_DIRECTORY is not set");
+                std::process::exit(2)
+            }
+        },
+        directory => directory.unwrap_or_default(),
+    };
+    let ca_cert = cli.ca_cert.unwrap_or_default();
+    let namespace = cli.namespace.unwrap_or_default();
+    let proxy = cli.proxy.unwrap_or_default();
+    let fallback_server = cli.fallback_server.unwrap_or_default();
+    let discover = cli.discover.unwrap_or_default();
+    let extra_headers: HashMap<String, String> = cli.headers.into_iter().collect();
+    let format = cli.format.unwrap_or_default();
+    let shell_keys = cli.shell_keys.unwrap_or_default();
+    let log_format = cli.log_format.unwrap_or_default();
+    let (debug, quiet, utc, raw, dry_run) = (cli.debug, cli.quiet, cli.utc, cli.raw, cli.dry_run);
+    let pretty = cli.pretty && !cli.compact;
+    let (insecure, constant_time, aad) = (cli.insecure, cli.constant_time, cli.aad);
+    let in_place = cli.in_place;
+    let verify_hmac = cli.verify_hmac;
+    let decompress = cli.decompress.unwrap_or_default();
+    let compression = if cli.no_compression { Some(false) } else { cli.compression };
+    let percent_encode = cli.percent_encode;
+    let user_agent = cli.user_agent.unwrap_or_default();
+    let request_id = cli.request_id.unwrap_or_default();
+
     if env_file.is_empty() {
-        env_file = std::env::var("env_file")
-            .unwrap_or(std::env::var("ENV_FILE")
+        env_file = env_var("env_file")
+            .unwrap_or(env_var("ENV_FILE")
                 .unwrap_or(".env".to_string()));
     }
     let env_file_path = std::env::current_dir()
         .unwrap_or_default()
         .join(env_file);
-    let _ = dotenv::from_path(env_file_path.as_path());
+    // Variables already set in the environment take precedence over the env file, which is optional
+    let loaded = if no_env { Ok(()) } else { dotenvy::from_path(env_file_path.as_path()) };
+    match loaded {
+        Ok(_) => {}
+        Err(dotenvy::Error::Io(err)) if err.kind() == std::io::ErrorKind::NotFound => {}
+        Err(err) => {
+            eprintln!("Failed to load env file {}: {}", env_file_path.display(), err);
+            std::process::exit(2)
+        }
+    }
+    let file = if config_file.is_empty() {
+        FileConfig::default()
+    } else {
+        load_config_file(&config_file)
+    };
     // Retrieve the API key from the environment
-    let apikey = get_env("APIKEY", "");
-    let vault_server_env = get_env("VAULT_SERVER", "");
-    let vault_server = match Url::parse(&vault_server_env) {
+    if apikey_stdin || !apikey_file.is_empty() {
+        apikey = read_input("apikey", apikey_stdin, &apikey_file);
+    }
+    let apikey = resolve(apikey, "APIKEY", file.apikey);
+    // Tables with their own apikey don't need the global one
+    let table_apikeys = file.apikeys.unwrap_or_default();
+    let target_table = if table_name.is_empty() { &get_table } else { &table_name };
+    let apikey = if table_apikeys.contains_key(target_table) { apikey } else { required("APIKEY", apikey) };
+    let apikey_previous = resolve(apikey_previous, "APIKEY_PREVIOUS", file.apikey_previous);
+    let vault_server_env = required("VAULT_SERVER", resolve(String::new(), "VAULT_SERVER", file.vault_server));
+    let vault_server = match parse_vault_server(&vault_server_env) {
         Ok(url) => url,
-        Err(_e) => panic!("Failed to parse vault address"),
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(2)
+        }
     };
-    println!("vault address: {}", &vault_server);
-    let transit_key_length = match std::env::var("TRANSMIT_KEY_LENGTH") {
-        Ok(value) => value.parse::<usize>().unwrap_or(TRANSIT_KEY_LENGTH),
-        Err(_) => TRANSIT_KEY_LENGTH,
+    let fallback_server = resolve(fallback_server, "VAULT_FALLBACK_SERVER", file.fallback_server);
+    let fallback_server = if fallback_server.is_empty() {
+        None
+    } else {
+        match parse_vault_server(&fallback_server) {
+            Ok(url) => Some(url),
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(2)
+            }
+        }
+    };
+    let transit_key_length = resolve_number(
+        "transit_key_length", String::new(), "TRANSMIT_KEY_LENGTH", file.transit_key_length, TRANSIT_KEY_LENGTH
+    );
+    let transit_time_bucket = resolve_number(
+        "transit_time_bucket", String::new(), "TRANSIT_TIME_BUCKET", file.transit_time_bucket, TRANSIT_TIME_BUCKET
+    );
+    let transit_tolerance = resolve_number(
+        "transit_tolerance", String::new(), "TRANSIT_TOLERANCE", file.transit_tolerance, TRANSIT_TOLERANCE
+    );
+    let transit_tag_length = resolve_number(
+        "transit_tag_length", String::new(), "TRANSIT_TAG_LENGTH", file.transit_tag_length, TRANSIT_TAG_LENGTH
+    );
+    let transit_epoch_override = resolve(
+        epoch_override, "TRANSIT_EPOCH_OVERRIDE", file.transit_epoch_override.map(|v| v.to_string())
+    );
+    let transit_epoch_override = if transit_epoch_override.is_empty() {
+        None
+    } else {
+        Some(parse_number("transit_epoch_override", &transit_epoch_override))
     };
-    let transit_time_bucket = match std::env::var("TRANSIT_TIME_BUCKET") {
-        Ok(value) => value.parse::<u64>().unwrap_or(TRANSIT_TIME_BUCKET),
-        Err(_) => TRANSIT_TIME_BUCKET
+    let cipher_suite: CipherSuite = resolve_choice(String::new(), "TRANSIT_CIPHER_SUITE", file.cipher_suite);
+    let hash_algorithm: HashAlgorithm = resolve_choice(String::new(), "TRANSIT_HASH_ALGORITHM", file.hash_algorithm);
+    let base64_variant: Base64Variant = resolve_choice(String::new(), "TRANSIT_BASE64_VARIANT", file.base64_variant);
+    let transit_derivation_order: DerivationOrder = resolve_choice(
+        String::new(), "TRANSIT_DERIVATION_ORDER", file.derivation_order
+    );
+    let transit_derivation_separator = or_default(
+        resolve(String::new(), "TRANSIT_DERIVATION_SEPARATOR", file.derivation_separator), TRANSIT_DERIVATION_SEPARATOR
+    );
+    let transit_constant_time = resolve_bool(constant_time, "TRANSIT_CONSTANT_TIME", file.transit_constant_time, false);
+    let transit_in_place = resolve_bool(in_place, "TRANSIT_IN_PLACE", file.transit_in_place, false);
+    let transit_verify_hmac = resolve_bool(verify_hmac, "TRANSIT_VERIFY_HMAC", file.transit_verify_hmac, false);
+    let transit_hmac_length = resolve_number(
+        "transit_hmac_length", String::new(), "TRANSIT_HMAC_LENGTH", file.transit_hmac_length, TRANSIT_HMAC_LENGTH
+    );
+    let transit_hmac_placement: HmacPlacement = resolve_choice(
+        String::new(), "TRANSIT_HMAC_PLACEMENT", file.transit_hmac_placement
+    );
+    let transit_decompress: PayloadCompression = resolve_choice(
+        decompress, "TRANSIT_DECOMPRESS", file.transit_decompress
+    );
+    let transit_aad = resolve_bool(aad, "TRANSIT_AAD", file.transit_aad, false);
+    let timeout = Duration::from_secs(resolve_number("timeout", timeout, "VAULT_TIMEOUT", file.timeout, TIMEOUT));
+    let http2_prior_knowledge = resolve_bool(
+        http2_prior_knowledge, "VAULT_HTTP2_PRIOR_KNOWLEDGE", file.http2_prior_knowledge, false
+    );
+    let tcp_keepalive = resolve_number("tcp_keepalive", tcp_keepalive, "VAULT_TCP_KEEPALIVE", file.tcp_keepalive, 0);
+    let tcp_keepalive = Some(Duration::from_secs(tcp_keepalive)).filter(|interval| !interval.is_zero());
+    let pool_idle_timeout = Some(Duration::from_secs(resolve_number(
+        "pool_idle_timeout", pool_idle_timeout, "VAULT_POOL_IDLE_TIMEOUT", file.pool_idle_timeout, POOL_IDLE_TIMEOUT
+    )));
+    let retries = resolve_number("retries", retries, "VAULT_RETRIES", file.retries, RETRIES);
+    let retry_backoff_ms = resolve_number(
+        "retry_backoff_ms", retry_backoff_ms, "VAULT_RETRY_BACKOFF_MS", file.retry_backoff_ms, RETRY_BACKOFF_MS
+    );
+    let request_jitter_ms = resolve_number(
+        "request_jitter_ms", request_jitter_ms, "VAULT_REQUEST_JITTER_MS", file.request_jitter_ms, REQUEST_JITTER_MS
+    );
+    let page_size = resolve_number("page_size", page_size, "VAULT_PAGE_SIZE", file.page_size, PAGE_SIZE);
+    let parallel_limit = resolve_number(
+        "parallel_limit", parallel_limit, "VAULT_PARALLEL_LIMIT", file.parallel_limit, PARALLEL_LIMIT
+    );
+    if parallel_limit == 0 {
+        eprintln!("parallel_limit must be at least 1, received: 0");
+        std::process::exit(2)
+    }
+    let accept = or_default(resolve(accept, "VAULT_ACCEPT", file.accept), ACCEPT);
+    let repeat_keys = resolve_bool(None, "VAULT_REPEAT_KEYS", file.repeat_keys, false);
+    let percent_encode = resolve_bool(percent_encode, "VAULT_PERCENT_ENCODE", file.percent_encode, false);
+    let cache_ttl = Duration::from_secs(resolve_number("cache_ttl", cache_ttl, "VAULT_CACHE_TTL", file.cache_ttl, 0));
+    let watch = Duration::from_secs(parse_number("watch", &or_default(watch, 0)));
+    if !watch.is_zero() && (!cipher.is_empty() || !decrypt_batch.is_empty() || health || dry_run || verify) {
+        eprintln!("--watch requires a command that retrieves secrets from the server, without --dry-run or --verify");
+        std::process::exit(2)
+    }
+    if verify && (health || dry_run || !decrypt_batch.is_empty() || list_tables || list_keys || !put_secret.is_empty() || !delete_secret.is_empty()) {
+        eprintln!("--verify requires a command that retrieves or decrypts secrets, without --dry-run");
+        std::process::exit(2)
+    }
+    let mode = if health {
+        Mode::Health
+    } else if !decrypt_batch.is_empty() {
+        Mode::DecryptBatch(decrypt_batch)
+    } else if watch_remote {
+        Mode::WatchRemote
+    } else if !watch.is_zero() {
+        Mode::Watch(watch)
+    } else if verify {
+        Mode::Verify
+    } else if dry_run && cipher.is_empty() {
+        Mode::DryRun
+    } else {
+        Mode::Once
     };
-    Config {
+    // Decrypted values are never exposed when verifying, not even in the debug logs
+    let log_secrets = cli.log_secrets && matches!(mode, Mode::Once | Mode::Watch(_) | Mode::WatchRemote);
+    let ca_cert = resolve(ca_cert, "VAULT_CA_CERT", file.ca_cert);
+    let insecure = resolve_bool(insecure, "VAULT_INSECURE", file.insecure, false);
+    // Enabled unless explicitly turned off
+    let compression = resolve_bool(compression, "VAULT_COMPRESSION", file.compression, true);
+    let user_agent = or_default(
+        resolve(user_agent, "VAULT_USER_AGENT", file.user_agent), format!("vaultapi-client/{}", metadata.pkg_version)
+    );
+    // A fixed ID only makes sense for a single invocation, so it isn't read from the config file
+    let request_id = resolve(request_id, "VAULT_REQUEST_ID", None);
+    let namespace = resolve(namespace, "VAULT_NAMESPACE", file.namespace);
+    let path_get_secret = or_default(resolve(String::new(), "VAULT_PATH_GET_SECRET", file.path_get_secret), PATH_GET_SECRET);
+    let path_get_secrets = or_default(resolve(String::new(), "VAULT_PATH_GET_SECRETS", file.path_get_secrets), PATH_GET_SECRETS);
+    let path_get_table = or_default(resolve(String::new(), "VAULT_PATH_GET_TABLE", file.path_get_table), PATH_GET_TABLE);
+    let path_put_secret = or_default(resolve(String::new(), "VAULT_PATH_PUT_SECRET", file.path_put_secret), PATH_PUT_SECRET);
+    let path_delete_secret = or_default(
+        resolve(String::new(), "VAULT_PATH_DELETE_SECRET", file.path_delete_secret), PATH_DELETE_SECRET
+    );
+    let path_list_tables = or_default(resolve(String::new(), "VAULT_PATH_LIST_TABLES", file.path_list_tables), PATH_LIST_TABLES);
+    let path_list_keys = or_default(resolve(String::new(), "VAULT_PATH_LIST_KEYS", file.path_list_keys), PATH_LIST_KEYS);
+    let path_watch = or_default(resolve(String::new(), "VAULT_PATH_WATCH", file.path_watch), PATH_WATCH);
+    let discover = resolve(discover, "VAULT_DISCOVER", file.discover);
+    // The proxy env vars are left to reqwest, which applies each of them to its own scheme
+    let proxy = if proxy.is_empty() { file.proxy.unwrap_or_default() } else { proxy };
+    let config = Config {
         vault_server,
+        fallback_server,
         apikey,
+        apikey_previous,
+        table_apikeys,
         transit_key_length,
         transit_time_bucket,
+        transit_tolerance,
+        transit_tag_length,
+        transit_epoch_override,
+        cipher_suite,
+        hash_algorithm,
+        base64_variant,
+        transit_derivation_order,
+        transit_derivation_separator,
+        transit_constant_time,
+        transit_in_place,
+        transit_verify_hmac,
+        transit_hmac_length,
+        transit_hmac_placement,
+        transit_decompress,
+        transit_aad,
+        timeout,
+        http2_prior_knowledge,
+        tcp_keepalive,
+        pool_idle_timeout,
+        retries,
+        retry_backoff_ms,
+        request_jitter_ms,
+        ca_cert,
+        insecure,
+        proxy,
+        no_env,
+        namespace,
+        path_get_secret,
+        path_get_secrets,
+        path_get_table,
+        path_put_secret,
+        path_delete_secret,
+        path_list_tables,
+        path_list_keys,
+        path_watch,
+        discover,
+        extra_headers,
+        accept,
+        compression,
+        user_agent,
+        request_id,
+        page_size,
+        repeat_keys,
+        percent_encode,
+        cache_ttl,
         cipher,
         table_name,
         get_secret,
         get_secrets,
         get_table,
+        put_secret,
+        put_value,
+        delete_secret,
+        list_tables,
+        list_keys,
+        parallel,
+        parallel_limit,
+        require_all,
+    };
+    let output = Output {
+        mode,
+        select,
+        flatten,
+        write_env,
+        keyring_store,
+        systemd_creds,
+        output,
+        output_mode,
+        raw,
+        pretty,
+        format,
+        shell_keys,
+        log_format,
+        debug,
+        quiet,
+        utc,
+        log_secrets,
+    };
+    (config, output)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Mutex, MutexGuard};
+
+    /// Serializes the tests setting environment variables, since `--no-env` applies to the whole process.
+    static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+    fn lock_env() -> MutexGuard<'static, ()> {
+        ENV_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn file_config(content: &str) -> Result<FileConfig, toml::de::Error> {
+        toml::from_str::<toml::Value>(content)?.try_into::<FileConfig>()
+    }
+
+    #[test]
+    fn settings_resolve_from_the_command_line_then_the_environment_then_the_config_file() {
+        let _guard = lock_env();
+        let file = || Some("file".to_string());
+        std::env::set_var("VAULTAPI_TEST_RESOLVE", "env");
+        assert_eq!(resolve("cli".to_string(), "VAULTAPI_TEST_RESOLVE", file()), "cli");
+        assert_eq!(resolve(String::new(), "VAULTAPI_TEST_RESOLVE", file()), "env");
+        std::env::remove_var("VAULTAPI_TEST_RESOLVE");
+        assert_eq!(resolve(String::new(), "VAULTAPI_TEST_RESOLVE", file()), "file");
+        assert_eq!(resolve(String::new(), "VAULTAPI_TEST_RESOLVE", None), "");
+    }
+
+    #[test]
+    fn boolean_settings_can_be_turned_off_from_the_command_line() {
+        let _guard = lock_env();
+        std::env::set_var("VAULTAPI_TEST_RESOLVE_BOOL", "true");
+        assert!(!resolve_bool(Some(false), "VAULTAPI_TEST_RESOLVE_BOOL", Some(true), false));
+        assert!(resolve_bool(Some(true), "VAULTAPI_TEST_RESOLVE_BOOL", Some(false), false));
+        assert!(resolve_bool(None, "VAULTAPI_TEST_RESOLVE_BOOL", Some(false), false));
+        std::env::set_var("VAULTAPI_TEST_RESOLVE_BOOL", "off");
+        assert!(!resolve_bool(None, "VAULTAPI_TEST_RESOLVE_BOOL", Some(true), true));
+        // An empty variable is unset, so the config file and then the default apply
+        std::env::set_var("VAULTAPI_TEST_RESOLVE_BOOL", "");
+        assert!(resolve_bool(None, "VAULTAPI_TEST_RESOLVE_BOOL", Some(true), false));
+        std::env::remove_var("VAULTAPI_TEST_RESOLVE_BOOL");
+        assert!(!resolve_bool(None, "VAULTAPI_TEST_RESOLVE_BOOL", Some(false), true));
+        assert!(resolve_bool(None, "VAULTAPI_TEST_RESOLVE_BOOL", None, true));
+        assert!(!resolve_bool(None, "VAULTAPI_TEST_RESOLVE_BOOL", None, false));
+    }
+
+    #[test]
+    fn numeric_settings_resolve_in_order_and_fall_back_to_the_default() {
+        let _guard = lock_env();
+        std::env::set_var("VAULTAPI_TEST_RESOLVE_NUMBER", "20");
+        assert_eq!(resolve_number("retries", "10".to_string(), "VAULTAPI_TEST_RESOLVE_NUMBER", Some(30), 40), 10);
+        assert_eq!(resolve_number("retries", String::new(), "VAULTAPI_TEST_RESOLVE_NUMBER", Some(30), 40), 20);
+        std::env::remove_var("VAULTAPI_TEST_RESOLVE_NUMBER");
+        assert_eq!(resolve_number("retries", String::new(), "VAULTAPI_TEST_RESOLVE_NUMBER", Some(30), 40), 30);
+        assert_eq!(resolve_number::<u32>("retries", String::new(), "VAULTAPI_TEST_RESOLVE_NUMBER", None, 40), 40);
+    }
+
+    #[test]
+    fn environment_is_skipped_with_no_env() {
+        let _guard = lock_env();
+        std::env::set_var("VAULTAPI_TEST_NO_ENV", "true");
+        IGNORE_ENV.store(true, Ordering::Relaxed);
+        let value = resolve(String::new(), "VAULTAPI_TEST_NO_ENV", Some("file".to_string()));
+        let enabled = resolve_bool(None, "VAULTAPI_TEST_NO_ENV", None, false);
+        IGNORE_ENV.store(false, Ordering::Relaxed);
+        std::env::remove_var("VAULTAPI_TEST_NO_ENV");
+        assert_eq!(value, "file");
+        assert!(!enabled);
+    }
+
+    #[test]
+    fn config_file_with_known_keys_is_loaded() {
+        let file = file_config("vault_server = \"http://localhost:8080\"\nretries = 5\ninsecure = true\n").unwrap();
+        assert_eq!(file.vault_server.as_deref(), Some("http://localhost:8080"));
+        assert_eq!(file.retries, Some(5));
+        assert_eq!(file.insecure, Some(true));
+    }
+
+    #[test]
+    fn config_file_with_an_unknown_key_is_rejected() {
+        let err = file_config("vault_server = \"http://localhost:8080\"\nretires = 5\n").unwrap_err();
+        assert!(err.message().contains("unknown field `retires`"), "{}", err.message());
+    }
+
+    #[test]
+    fn interpolate_expands_a_set_variable() {
+        let _guard = lock_env();
+        std::env::set_var("VAULTAPI_TEST_HOST", "http://vault:8080");
+        assert_eq!(interpolate("${VAULTAPI_TEST_HOST}/api/").unwrap(), "http://vault:8080/api/");
+        assert_eq!(interpolate("${VAULTAPI_TEST_HOST:-http://localhost}").unwrap(), "http://vault:8080");
+        std::env::remove_var("VAULTAPI_TEST_HOST");
+    }
+
+    #[test]
+    fn interpolate_falls_back_to_the_default_of_an_unset_or_empty_variable() {
+        let _guard = lock_env();
+        std::env::remove_var("VAULTAPI_TEST_UNSET");
+        assert_eq!(interpolate("${VAULTAPI_TEST_UNSET:-http://localhost}/").unwrap(), "http://localhost/");
+        assert_eq!(interpolate("${VAULTAPI_TEST_UNSET:-}").unwrap(), "");
+        std::env::set_var("VAULTAPI_TEST_EMPTY", "");
+        assert_eq!(interpolate("${VAULTAPI_TEST_EMPTY:-fallback}").unwrap(), "fallback");
+        std::env::remove_var("VAULTAPI_TEST_EMPTY");
+    }
+
+    #[test]
+    fn interpolate_keeps_literal_dollar_signs() {
+        let _guard = lock_env();
+        assert_eq!(interpolate("pa$$word").unwrap(), "pa$word");
+        assert_eq!(interpolate("$$${VAULTAPI_TEST_UNSET:-x}").unwrap(), "$x");
+        assert_eq!(interpolate("cost: 5$ or $HOME").unwrap(), "cost: 5$ or $HOME");
+        assert_eq!(interpolate("trailing $").unwrap(), "trailing $");
+    }
+
+    #[test]
+    fn interpolate_rejects_an_unterminated_reference() {
+        let _guard = lock_env();
+        let err = interpolate("${VAULTAPI_TEST_HOST/api/").unwrap_err();
+        assert!(err.starts_with("Unterminated variable reference"), "{}", err);
+    }
+
+    #[test]
+    fn interpolate_rejects_an_unset_variable_without_a_default() {
+        let _guard = lock_env();
+        std::env::remove_var("VAULTAPI_TEST_UNSET");
+        assert_eq!(interpolate("${VAULTAPI_TEST_UNSET}").unwrap_err(), "Environment variable VAULTAPI_TEST_UNSET is not set");
+    }
+
+    #[test]
+    fn interpolate_treats_every_variable_as_unset_with_no_env() {
+        let _guard = lock_env();
+        std::env::set_var("VAULTAPI_TEST_IGNORED", "from-env");
+        IGNORE_ENV.store(true, Ordering::Relaxed);
+        let defaulted = interpolate("${VAULTAPI_TEST_IGNORED:-from-default}");
+        let required = interpolate("${VAULTAPI_TEST_IGNORED}");
+        IGNORE_ENV.store(false, Ordering::Relaxed);
+        std::env::remove_var("VAULTAPI_TEST_IGNORED");
+        assert_eq!(defaulted.unwrap(), "from-default");
+        assert_eq!(required.unwrap_err(), "Environment variable VAULTAPI_TEST_IGNORED is not set");
+    }
+
+    #[test]
+    fn interpolate_values_expands_nested_tables_and_arrays() {
+        let _guard = lock_env();
+        std::env::set_var("VAULTAPI_TEST_KEY", "table-key");
+        let mut value = toml::from_str::<toml::Value>(
+            "retries = 5\nlist = [\"${VAULTAPI_TEST_KEY}\"]\n[apikeys]\nusers = \"${VAULTAPI_TEST_KEY}\"\n"
+        ).unwrap();
+        interpolate_values(&mut value).unwrap();
+        std::env::remove_var("VAULTAPI_TEST_KEY");
+        assert_eq!(value["apikeys"]["users"].as_str(), Some("table-key"));
+        assert_eq!(value["list"][0].as_str(), Some("table-key"));
+        assert_eq!(value["retries"].as_integer(), Some(5));
     }
 }
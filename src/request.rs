@@ -1,168 +1,1900 @@
+use crate::atomic;
+use crate::auth::AuthContext;
+use crate::client::VaultClient;
 use crate::decipher;
-use crate::parser::Config;
-use reqwest::blocking::Client;
+use crate::error::{with_request_id, ConfigError, ConnectionError, Error};
+use crate::config::Config;
+use futures_util::StreamExt;
+use reqwest::header::{HeaderMap, CACHE_CONTROL, CONTENT_TYPE, RETRY_AFTER};
+use reqwest::{Method, StatusCode};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::collections::HashMap;
-use std::process::exit;
+use std::collections::{HashMap, HashSet};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
 
 
+/// Header carrying the ID of a request, shared by its retries, to correlate it with the server logs.
+const REQUEST_ID_HEADER: &str = "X-Request-ID";
+
+/// Longest wait honored from a `Retry-After` header, so a misbehaving server can't park the client indefinitely.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+/// Interval in bytes at which the progress of a download to file is logged.
+const PROGRESS_INTERVAL: u64 = 1024 * 1024;
+
+/// Writer that counts the bytes written, and logs the progress at every `PROGRESS_INTERVAL`.
+struct ProgressWriter<W: Write> {
+    inner: W,
+    written: u64,
+    logged: u64,
+}
+
+impl<W: Write> Write for ProgressWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let count = self.inner.write(buf)?;
+        self.written += count as u64;
+        if self.written - self.logged >= PROGRESS_INTERVAL {
+            self.logged = self.written;
+            log::info!("{} bytes written", self.written);
+        }
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Rows of one page of a table, as returned by the server.
+struct TablePage {
+    rows: serde_json::Map<String, Value>,
+    /// Whether the rows are still encrypted, when the server returned one ciphertext per row.
+    encrypted: bool,
+    /// Page to request next, `None` after the last page.
+    next: Option<String>,
+}
+
+/// Rows of a table, retrieved page by page and decrypted one at a time, see `VaultClient::get_table_iter`.
+struct TableRows<'a> {
+    vault: &'a VaultClient,
+    table_name: String,
+    rows: serde_json::map::IntoIter,
+    encrypted: bool,
+    /// Keys yielded so far, to skip the rows of a page repeated by the server and end the pagination on it.
+    seen: HashSet<String>,
+    /// Page to request once the current one is exhausted, `None` after the last page or a failed request.
+    next: Option<String>,
+}
+
+impl Iterator for TableRows<'_> {
+    type Item = Result<(String, Value), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((key, value)) = self.rows.next() {
+                if !self.encrypted {
+                    return Some(Ok((key, value)));
+                }
+                return match self.vault.decrypt_detail(value, &self.table_name) {
+                    Ok(value) => Some(Ok((key, value))),
+                    Err(err) => {
+                        log::warn!("Failed to decrypt the row {}: {}", key, err);
+                        Some(Err(err))
+                    }
+                };
+            }
+            let page = self.next.take()?;
+            match self.vault.block_on(self.vault.table_page_async(&self.table_name, &page, &mut self.seen)) {
+                Ok(page) => {
+                    self.rows = page.rows.into_iter();
+                    self.encrypted = page.encrypted;
+                    self.next = page.next;
+                }
+                Err(err) => return Some(Err(err)),
+            }
+        }
+    }
+}
+
+/// Picks the page to request after the current one of a paginated table.
+///
+/// A `next` field in the response is followed as is, and a `null` value ends the pagination. Otherwise, the
+/// following page number is requested, unless the current page was short. Either way, the pagination ends on
+/// a page that only repeated the rows received before, or a `next` pointing back at the current page,
+/// eg: for a server ignoring the `page` parameter.
+///
+/// # Arguments
+/// * `envelope` - Full response of the current page.
+/// * `page` - Current page.
+/// * `received` - Number of rows in the current page.
+/// * `page_size` - Number of rows requested per page.
+/// * `repeated` - Whether the current page added no rows to those received before.
+///
+/// # Returns
+/// * An `Option<String>` with the next page, or `None` after the last page.
+fn next_page(envelope: &Value, page: &str, received: usize, page_size: usize, repeated: bool) -> Option<String> {
+    if repeated && received > 0 {
+        log::warn!("Page {} only repeated the rows received before, ending the pagination", page);
+        return None;
+    }
+    let next = match envelope.get("next") {
+        Some(Value::String(token)) => token.to_string(),
+        Some(Value::Number(number)) => number.to_string(),
+        Some(_) => return None,
+        None if received < page_size => return None,
+        None => (page.parse::<u64>().unwrap_or(1) + 1).to_string(),
+    };
+    if next == page {
+        log::warn!("Page {} points back at itself as the next page, ending the pagination", page);
+        return None;
+    }
+    Some(next)
+}
+
+/// Query parameters of a request, preserving the insertion order and allowing repeated keys.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueryParams {
+    pairs: Vec<(String, String)>,
+}
+
+impl QueryParams {
+    /// Creates an empty set of query parameters.
+    pub fn new() -> Self {
+        QueryParams::default()
+    }
+
+    /// Appends a parameter, keeping any previous values of the same key.
+    ///
+    /// # Arguments
+    /// * `key` - Name of the parameter.
+    /// * `value` - Value of the parameter.
+    ///
+    /// # Returns
+    /// * The `QueryParams` object, to chain further parameters.
+    pub fn with(mut self, key: &str, value: &str) -> Self {
+        self.push(key, value);
+        self
+    }
+
+    /// Appends a parameter in place, keeping any previous values of the same key.
+    ///
+    /// # Arguments
+    /// * `key` - Name of the parameter.
+    /// * `value` - Value of the parameter.
+    pub fn push(&mut self, key: &str, value: &str) {
+        self.pairs.push((key.to_string(), value.to_string()));
+    }
+
+    /// Retrieves the first value of a parameter.
+    ///
+    /// # Arguments
+    /// * `key` - Name of the parameter.
+    ///
+    /// # Returns
+    /// * An `Option<&str>` containing the value, `None` if the parameter is missing.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.pairs.iter().find(|(name, _)| name == key).map(|(_, value)| value.as_str())
+    }
+
+    /// Iterates over the parameters in insertion order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.pairs.iter().map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    /// Checks if there are no parameters.
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    /// Appends the parameters to the query of a URL, after checking them for control characters.
+    ///
+    /// By default, the parameters are form-encoded, where a space becomes `+`. With `percent_encode`, every
+    /// character other than the unreserved `A-Z a-z 0-9 - . _ ~` is percent-encoded instead, eg: a space as `%20`,
+    /// for servers that don't decode `+`, or mishandle a `/` or `#` in the table and key names.
+    ///
+    /// # Arguments
+    /// * `url` - URL to append the parameters to.
+    /// * `percent_encode` - Percent-encode the parameters instead of form-encoding them.
+    ///
+    /// # Returns
+    /// * A `Result<(), Error>` indicating success, or an error naming the parameter with a control character.
+    pub fn append_to(&self, url: &mut reqwest::Url, percent_encode: bool) -> Result<(), Error> {
+        if let Some((key, value)) = self.iter().find(|(_, value)| value.chars().any(char::is_control)) {
+            return Err(Error::Config(format!("Invalid {} {:?}, control characters are not allowed", key, value)));
+        }
+        if !percent_encode {
+            url.query_pairs_mut().extend_pairs(self.iter());
+            return Ok(());
+        }
+        let mut query: Vec<String> = url.query().filter(|query| !query.is_empty()).map(str::to_string).into_iter().collect();
+        query.extend(self.iter().map(|(key, value)| format!("{}={}", encode_component(key), encode_component(value))));
+        url.set_query(Some(&query.join("&")));
+        Ok(())
+    }
+}
+
+/// Percent-encodes every byte of a query component, other than the unreserved characters of RFC 3986.
+///
+/// # Arguments
+/// * `component` - Name or value of a query parameter.
+///
+/// # Returns
+/// * A `String` that is safe to use in a URL query as is.
+fn encode_component(component: &str) -> String {
+    component
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
 struct RequestMaterials {
+    method: Method,
     url: String,
-    params: HashMap<String, String>,
+    params: QueryParams,
     headers: HashMap<String, String>,
+    table_name: String,
+}
+
+
+/// Status and version reported by the VaultAPI server's health endpoint.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ServerInfo {
+    #[serde(default)]
+    pub status: String,
+    #[serde(default)]
+    pub version: String,
+}
+
+/// Decrypted secrets along with the ciphertext they were decrypted from, for auditing.
+#[derive(Debug, Clone)]
+pub struct CipheredSecret {
+    /// Base64 ciphertext as returned by the server, or an object mapping each key to its ciphertext for `get-secrets`.
+    pub cipher: Value,
+    /// Deciphered content.
+    pub value: Value,
+}
+
+
+/// Extracts the server's message from an error object in the `detail` field, eg: `{"error": "invalid key"}`.
+///
+/// # Arguments
+/// * `detail` - Value of the `detail` field.
+///
+/// # Returns
+/// * An `Option<String>` containing the error message, if the value has the shape of an error object.
+fn server_error(detail: &Value) -> Option<String> {
+    let map = detail.as_object()?;
+    // Objects with any other key are secrets, which may well include an "error" key of their own
+    if !map.keys().all(|key| ["error", "message", "code", "status"].contains(&key.as_str())) {
+        return None;
+    }
+    ["error", "message"]
+        .iter()
+        .find_map(|key| map.get(*key).and_then(Value::as_str))
+        .map(str::to_string)
+}
+
+/// Collects the names returned by the list endpoints.
+///
+/// # Arguments
+/// * `detail` - Value of the `detail` field, an array of names or an object keyed by name.
+///
+/// # Returns
+/// * A `Result<Vec<String>, Error>` containing the names, empty if the server has none.
+fn names(detail: Value) -> Result<Vec<String>, Error> {
+    if let Some(message) = server_error(&detail) {
+        return Err(Error::Response(format!("Server returned an error: {}", message)));
+    }
+    match detail {
+        Value::Null => Ok(Vec::new()),
+        Value::Array(items) => items
+            .into_iter()
+            .map(|item| match item {
+                Value::String(name) => Ok(name),
+                other => Err(Error::Response(format!("Expected a list of names, received: {}", other))),
+            })
+            .collect(),
+        Value::Object(map) => Ok(map.into_iter().map(|(name, _)| name).collect()),
+        other => Err(Error::Response(format!("Expected a list of names, received: {}", other))),
+    }
+}
+
+/// Metadata of a server response, alongside its JSON body.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResponseMetadata {
+    /// Duration the response can be cached for, from its `Cache-Control` header.
+    /// `Some(0)` for `no-store` or `no-cache`, `None` when the header is absent or unparseable.
+    pub max_age: Option<Duration>,
+}
+
+impl ResponseMetadata {
+    /// Extracts the metadata from the response headers.
+    fn from_headers(headers: &HeaderMap) -> Self {
+        ResponseMetadata { max_age: cache_max_age(headers) }
+    }
 }
 
+/// Parses the cache lifetime of a response from its `Cache-Control` header, eg: `max-age=60`.
+///
+/// # Arguments
+/// * `headers` - Response headers.
+///
+/// # Returns
+/// * An `Option<Duration>` containing the lifetime, zero when caching is forbidden, or `None` if not provided.
+fn cache_max_age(headers: &HeaderMap) -> Option<Duration> {
+    let directives = headers.get(CACHE_CONTROL)?.to_str().ok()?.to_lowercase();
+    let mut max_age = None;
+    for directive in directives.split(',').map(str::trim) {
+        match directive.split_once('=') {
+            _ if directive == "no-store" || directive == "no-cache" => return Some(Duration::ZERO),
+            Some(("max-age", seconds)) => max_age = seconds.trim_matches('"').parse().ok().map(Duration::from_secs),
+            _ => {}
+        }
+    }
+    max_age
+}
+
+/// Classifies a failed request by walking the chain of its underlying errors.
+///
+/// # Arguments
+/// * `url` - Server URL the request was sent to.
+/// * `err` - Error returned by `reqwest`.
+/// * `timeout` - Configured request timeout, reported for timeouts.
+/// * `request_id` - Value of the `X-Request-ID` header sent.
+///
+/// # Returns
+/// * A `ConnectionError` carrying the innermost error message, which names the actual cause.
+fn connection_error(url: &str, err: &reqwest::Error, timeout: Duration, request_id: &str) -> ConnectionError {
+    let (url, request_id) = (url.to_string(), request_id.to_string());
+    if err.is_timeout() {
+        return ConnectionError::Timeout { url, timeout, request_id };
+    }
+    let mut refused = false;
+    let mut message = String::new();
+    // Debug output names the error types, eg: `Ssl(...)` for TLS errors whose message is only "unexpected EOF"
+    let mut trace = String::new();
+    let mut source: Option<&(dyn std::error::Error + 'static)> = Some(err);
+    while let Some(cause) = source {
+        if let Some(io_error) = cause.downcast_ref::<std::io::Error>() {
+            refused |= io_error.kind() == std::io::ErrorKind::ConnectionRefused;
+        }
+        message = cause.to_string();
+        trace.push_str(&format!("{} {:?}\n", message, cause).to_lowercase());
+        source = cause.source();
+    }
+    let mentions = |words: &[&str]| words.iter().any(|word| trace.contains(word));
+    if refused {
+        ConnectionError::Refused { url, message, request_id }
+    } else if mentions(&["dns error", "failed to lookup address", "name or service not known"]) {
+        ConnectionError::Dns { url, message, request_id }
+    } else if mentions(&["tls", "ssl", "certificate", "handshake"]) {
+        ConnectionError::Tls { url, message, request_id }
+    } else {
+        ConnectionError::Other { url, message, request_id }
+    }
+}
 
-/// Constructs authentication headers.
+/// Builds the error for a non-2xx response, preferring the server's message over the raw body.
 ///
 /// # Arguments
-/// * `apikey` - APIkey to authenticate the server.
+/// * `status` - Response status code.
+/// * `body` - Raw response body.
 ///
 /// # Returns
-/// * A `HashMap<String, String>` containing auth headers.
-fn auth_headers(apikey: &String) -> HashMap<String, String> {
+/// * An `Error` including the status code, and the server's message or the body truncated to 256 characters.
+fn status_error(status: StatusCode, body: &str) -> Error {
+    let detail = serde_json::from_str::<Value>(body)
+        .ok()
+        .and_then(|json| json.get("detail").cloned());
+    let message = match detail {
+        Some(Value::String(message)) => message,
+        Some(detail) => match server_error(&detail) {
+            Some(message) => message,
+            None => detail.to_string(),
+        },
+        None if body.chars().count() > 256 => format!("{}...", body.chars().take(256).collect::<String>()),
+        None => body.to_string(),
+    };
+    Error::Status {
+        code: status.as_u16(),
+        message: format!("Server responded with {}: {}", status, message),
+    }
+}
+
+/// Upper bound of the delay between the reconnections of `watch_remote`.
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// Lifetime of a single `watch_remote` connection, after which it is re-established with the last event ID,
+/// as the configured timeout applies to the whole response.
+const WATCH_CONNECTION_TIMEOUT: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Event of a `text/event-stream` response.
+#[derive(Debug, Default)]
+struct ServerSentEvent {
+    id: Option<String>,
+    data: String,
+}
+
+/// Incremental parser of a `text/event-stream` response, fed with the chunks as they arrive.
+#[derive(Debug, Default)]
+struct EventStream {
+    buffer: Vec<u8>,
+    event: ServerSentEvent,
+    has_data: bool,
+}
+
+impl EventStream {
+    /// Parses the complete lines of a chunk, keeping the trailing partial line for the next one.
+    ///
+    /// # Arguments
+    /// * `chunk` - Bytes of the response, split anywhere, even within a UTF-8 character.
+    ///
+    /// # Returns
+    /// * A `Vec<ServerSentEvent>` containing the events completed by a blank line, without the comments.
+    fn feed(&mut self, chunk: &[u8]) -> Vec<ServerSentEvent> {
+        self.buffer.extend_from_slice(chunk);
+        let mut events = Vec::new();
+        while let Some(end) = self.buffer.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\n', '\r']);
+            if line.is_empty() {
+                let event = std::mem::take(&mut self.event);
+                if std::mem::take(&mut self.has_data) {
+                    events.push(event);
+                }
+                continue;
+            }
+            let (field, value) = match line.split_once(':') {
+                Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+                None => (line, ""),
+            };
+            match field {
+                // Comments keep the connection alive, without an event
+                "" => {}
+                "data" => {
+                    if self.has_data {
+                        self.event.data.push('\n');
+                    }
+                    self.event.data.push_str(value);
+                    self.has_data = true;
+                }
+                "id" => self.event.id = Some(value.to_string()),
+                _ => {}
+            }
+        }
+        events
+    }
+}
+
+/// Generates a random version 4 UUID, eg: to identify a request in the server logs.
+///
+/// # Returns
+/// * A `String` containing the hyphenated UUID, or zeros if the system random generator fails.
+fn new_request_id() -> String {
+    let mut bytes = [0u8; 16];
+    if SystemRandom::new().fill(&mut bytes).is_ok() {
+        // Version 4 in the high nibble of the 7th byte, and the RFC 4122 variant in the 9th byte
+        bytes[6] = (bytes[6] & 0x0f) | 0x40;
+        bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    }
+    let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+/// Adds the request ID to the message of a failed request, see `with_request_id`.
+///
+/// # Arguments
+/// * `err` - Error of the request, where connection errors already carry the request ID.
+/// * `request_id` - Value of the `X-Request-ID` header sent.
+///
+/// # Returns
+/// * The `Error` with the request ID in its message.
+fn traced(err: Error, request_id: &str) -> Error {
+    match err {
+        Error::Status { code, message } => Error::Status { code, message: with_request_id(message, request_id) },
+        Error::Request(message) => Error::Request(with_request_id(message, request_id)),
+        Error::Response(message) => Error::Response(with_request_id(message, request_id)),
+        err => err,
+    }
+}
+
+/// Constructs the headers for a request, along with any extra headers from the config.
+///
+/// The authentication is left to the client's `Auth`, applied when the request is sent.
+///
+/// # Arguments
+/// * `config` - Config object to retrieve the accepted media types, the namespace and the extra headers.
+///
+/// # Returns
+/// * A `Result<HashMap<String, String>, Error>` containing the headers, or an error if an extra header
+///   collides with a built-in header.
+fn request_headers(config: &Config) -> Result<HashMap<String, String>, Error> {
     let mut headers = HashMap::new();
-    let bearer = format!("Bearer {}", apikey);
-    headers.insert("Authorization".to_string(), bearer);
-    headers.insert("Accept".to_string(), "application/json".to_string());
-    headers
+    headers.insert("Accept".to_string(), config.accept.to_string());
+    if !config.namespace.is_empty() {
+        headers.insert("X-Vault-Namespace".to_string(), config.namespace.to_string());
+    }
+    for (key, value) in &config.extra_headers {
+        // Authorization is reserved for the authentication scheme
+        if key.eq_ignore_ascii_case("Authorization") || headers.keys().any(|existing| existing.eq_ignore_ascii_case(key)) {
+            return Err(Error::Config(format!("Extra header {} collides with a built-in header", key)));
+        }
+        headers.insert(key.to_string(), value.to_string());
+    }
+    Ok(headers)
 }
 
 
+/// Splits the comma separated keys, trimming each key, dropping empties and duplicates.
+///
+/// # Arguments
+/// * `keys` - Comma separated list of secret keys.
+///
+/// # Returns
+/// * A `Result<Vec<String>, Error>` containing the keys in their original order, or an error if a key has
+///   characters other than letters, digits, `_`, `-` and `.`, or if no keys are left.
+pub(crate) fn normalize_keys(keys: &str) -> Result<Vec<String>, Error> {
+    let mut normalized: Vec<String> = Vec::new();
+    for key in keys.split(',').map(str::trim).filter(|key| !key.is_empty()) {
+        if !key.chars().all(|c| c.is_ascii_alphanumeric() || "_-.".contains(c)) {
+            return Err(Error::Config(format!("Invalid secret key: {}", key)));
+        }
+        if !normalized.iter().any(|existing| existing == key) {
+            normalized.push(key.to_string());
+        }
+    }
+    if normalized.is_empty() {
+        return Err(Error::Config(format!("No secret keys found in: '{}'", keys)));
+    }
+    Ok(normalized)
+}
+
 /// Constructs the required fields to make a request.
 ///
 /// # Arguments
 /// * `config` - Config object to retrieve environment variables, and command line arguments.
 ///
 /// # Returns
-/// * A `RequestMaterials` struct containing auth headers, query parameters, and the request URL.
-fn create_request_materials(config: &Config) -> RequestMaterials {
+/// * A `Result<RequestMaterials, Error>` containing auth headers, query parameters, and the request URL.
+fn create_request_materials(config: &Config) -> Result<RequestMaterials, Error> {
     // Add URL parameters
-    let mut url = String::new();
-    let mut params = HashMap::new();
-
-    if !config.table_name.is_empty() {
-        params.insert("table_name".to_string(), config.table_name.to_string());
+    let table_name = if !config.table_name.is_empty() {
+        &config.table_name
     } else if !config.get_table.is_empty() {
-        params.insert("table_name".to_string(), config.get_table.to_string());
+        &config.get_table
     } else {
-        println!("Table name is mandatory to retrieve the secret");
-        exit(1)
-    }
+        return Err(ConfigError::MissingTable.into());
+    };
+    let mut params = QueryParams::new().with("table_name", table_name);
 
-    if !config.get_secrets.is_empty() {
-        url = format!("{}get-secrets", &config.vault_server);
-        params.insert("keys".to_string(), config.get_secrets.to_string());
+    let url = if !config.get_secrets.is_empty() {
+        let keys = normalize_keys(&config.get_secrets)?;
+        if config.repeat_keys {
+            for key in &keys {
+                params.push("keys", key);
+            }
+        } else {
+            params.push("keys", &keys.join(","));
+        }
+        config.endpoint(&config.path_get_secrets)
     } else if !config.get_secret.is_empty() {
-        url = format!("{}get-secret", &config.vault_server);
-        params.insert("key".to_string(), config.get_secret.to_string());
-    } else if !config.get_table.is_empty() {
-        url = format!("{}get-table", &config.vault_server);
-    } else if config.table_name.is_empty() {
-        println!("Required parameters unfilled!");
-        exit(1)
-    }
-    RequestMaterials {
+        params.push("key", &config.get_secret);
+        config.endpoint(&config.path_get_secret)
+    } else {
+        // Without any keys, the whole table is retrieved
+        config.endpoint(&config.path_get_table)
+    };
+    Ok(RequestMaterials {
+        method: Method::GET,
         url,
         params,
-        headers: auth_headers(&config.apikey),
+        headers: request_headers(config)?,
+        table_name: table_name.to_string(),
+    })
+}
+
+/// Finds the requested keys that are absent from the retrieved secrets.
+///
+/// # Arguments
+/// * `keys` - Requested keys, in their original order.
+/// * `secrets` - Decrypted object of the secrets returned by the server.
+///
+/// # Returns
+/// * A `Vec<String>` containing the missing keys in the requested order, all of them if `secrets` isn't an object.
+fn missing_keys(keys: &[String], secrets: &Value) -> Vec<String> {
+    keys.iter()
+        .filter(|key| secrets.get(key.as_str()).is_none())
+        .cloned()
+        .collect()
+}
+
+/// Picks a random number of milliseconds below the upper bound.
+///
+/// # Arguments
+/// * `max_ms` - Exclusive upper bound in milliseconds.
+///
+/// # Returns
+/// * A `u64` between `0` and `max_ms`, `0` if the bound is `0` or randomness is unavailable.
+fn random_millis(max_ms: u64) -> u64 {
+    let mut bytes = [0u8; 8];
+    match SystemRandom::new().fill(&mut bytes) {
+        Ok(_) if max_ms > 0 => u64::from_le_bytes(bytes) % max_ms,
+        _ => 0,
     }
 }
 
-/// Function to create a server request and process the response.
+/// Computes the exponential backoff delay for a retry attempt, with random jitter.
 ///
 /// # Arguments
-/// * `config` - Config object to retrieve environment variables, and command line arguments.
+/// * `base_delay_ms` - Base delay in milliseconds.
+/// * `attempt` - Retry attempt number, starting at 1.
 ///
 /// # Returns
-/// * A `Result<Value, String>` containing deciphered content.
-pub fn server_connection(config: &Config) -> Result<Value, String> {
-    let request = create_request_materials(config);
-    let response = make_request(
-        &request.url,
-        Some(request.headers),
-        Some(request.params)
-    );
-    // Check if the result is the expected "detail" field, or handle accordingly
-    match response {
-        Value::Null => {
-            println!("No 'detail' key found in the response.");
-            exit(1)
-        }
-        Value::String(cipher_text) => {
-            return decipher::transit_decrypt(
-                &config.apikey,
-                &cipher_text,
-                config.transit_key_length,
-                config.transit_time_bucket,
-            )
-        }
-        Value::Object(obj) => {
-            println!("Detail is an object: {:?}", obj);
-        }
-        _ => {
-            println!("Unexpected value returned: {:?}", response);
-        }
+/// * A `Duration` to wait before the next attempt.
+fn backoff_delay(base_delay_ms: u64, attempt: u32) -> Duration {
+    let exponential = base_delay_ms.saturating_mul(2u64.saturating_pow(attempt - 1));
+    Duration::from_millis(exponential.saturating_add(random_millis(base_delay_ms)))
+}
+
+/// Parses a response body according to its `Content-Type`, MessagePack if negotiated, JSON otherwise.
+///
+/// # Arguments
+/// * `headers` - Response headers.
+/// * `body` - Raw response body.
+///
+/// # Returns
+/// * A `Result<Value, Error>` containing the parsed body.
+fn parse_body(headers: &HeaderMap, body: &[u8]) -> Result<Value, Error> {
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_lowercase();
+    if content_type.contains("msgpack") {
+        #[cfg(feature = "msgpack")]
+        return rmp_serde::from_slice(body)
+            .map_err(|err| Error::Request(format!("Failed to parse response as MessagePack: {}", err)));
+        #[cfg(not(feature = "msgpack"))]
+        return Err(Error::Response(
+            "Server responded with MessagePack, which requires the msgpack feature".to_string()
+        ));
     }
-    exit(1)
+    serde_json::from_slice(body).map_err(|err| Error::Request(format!("Failed to parse response as JSON: {}", err)))
 }
 
-/// Function to make a `GET` request to the server.
+/// Parses the `Retry-After` header, which can either be a number of seconds or an HTTP-date.
+///
+/// Waits longer than `MAX_RETRY_AFTER` are clamped to it, with a warning.
 ///
 /// # Arguments
-/// * `server_url` - Server URL.
-/// * `headers` - Authentication headers.
-/// * `params` - Query parameters.
+/// * `headers` - Response headers.
 ///
 /// # Returns
-/// * A `Value` object containing the server response.
-pub fn make_request(
-    server_url: &str,
-    headers: Option<HashMap<String, String>>,
-    params: Option<HashMap<String, String>>,
-) -> Value {
-    // Create a reqwest client
-    let client = Client::new();
+/// * An `Option<Duration>` to wait before the next attempt, if the header is present and valid.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    let delay = match value.parse::<u64>() {
+        Ok(seconds) => Duration::from_secs(seconds),
+        Err(_) => {
+            let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+            let remaining = date.with_timezone(&chrono::Utc) - chrono::Utc::now();
+            remaining.to_std().unwrap_or(Duration::ZERO)
+        }
+    };
+    if delay > MAX_RETRY_AFTER {
+        log::warn!("Retry-After of {:?} exceeds {:?}, retrying after {:?} instead", delay, MAX_RETRY_AFTER, MAX_RETRY_AFTER);
+        return Some(MAX_RETRY_AFTER);
+    }
+    Some(delay)
+}
 
-    // Build the URL with parameters if provided
-    let mut url = reqwest::Url::parse(server_url).expect("Invalid URL");
-    if let Some(query_params) = params {
-        let query: Vec<(String, String)> = query_params.into_iter().collect();
-        url.query_pairs_mut().extend_pairs(query);
+impl VaultClient {
+    /// Function to create a server request and process the response.
+    ///
+    /// Blocking wrapper around `server_connection_async`, which must not be called from within an async runtime.
+    ///
+    /// # Returns
+    /// * A `Result<Value, Error>` containing deciphered content.
+    pub fn server_connection(&self) -> Result<Value, Error> {
+        self.block_on(self.server_connection_async())
     }
 
-    // Prepare the request builder
-    let mut request = client.get(url);
+    /// Async function to create a server request and process the response.
+    ///
+    /// # Returns
+    /// * A `Result<Value, Error>` containing deciphered content.
+    pub async fn server_connection_async(&self) -> Result<Value, Error> {
+        let config = &self.config;
+        if !config.delete_secret.is_empty() {
+            return self.delete_secret_async(&config.table_name, &config.delete_secret).await;
+        }
+        if config.list_tables || config.list_keys {
+            let names = if config.list_tables {
+                self.list_tables_async().await?
+            } else {
+                self.list_keys_async(&config.table_name).await?
+            };
+            return Ok(Value::from(names));
+        }
+        if !config.put_secret.is_empty() {
+            // Values that aren't valid JSON are stored as plain strings
+            let value = serde_json::from_str(&config.put_value)
+                .unwrap_or_else(|_| Value::String(config.put_value.to_string()));
+            return self.put_secret_async(&config.table_name, &config.put_secret, &value).await;
+        }
+        let request = create_request_materials(config)?;
+        if config.get_secrets.is_empty() {
+            let table_name = request.params.get("table_name").unwrap_or_default();
+            if !config.get_secret.is_empty() {
+                return self.get_secret_async(table_name, &config.get_secret).await;
+            }
+            if !config.get_table.is_empty() {
+                return self.get_table_async(table_name).await;
+            }
+        }
+        let table_name = request.params.get("table_name").unwrap_or_default().to_string();
+        let response = self.make_table_request_async(
+            Method::GET,
+            &request.url,
+            &request.table_name,
+            Some(request.headers),
+            Some(request.params)
+        ).await?;
+        let secrets = self.decrypt_response(response, &table_name)?;
+        if config.require_all {
+            let missing = missing_keys(&normalize_keys(&config.get_secrets)?, &secrets);
+            if !missing.is_empty() {
+                return Err(Error::MissingKeys(missing));
+            }
+        }
+        Ok(secrets)
+    }
+
+    /// Function to retrieve the secrets, and return the ciphertext along with the deciphered content.
+    ///
+    /// Blocking wrapper around `server_connection_with_cipher_async`, which must not be called from within an async runtime.
+    ///
+    /// # Returns
+    /// * A `Result<CipheredSecret, Error>` containing the ciphertext and the deciphered content.
+    pub fn server_connection_with_cipher(&self) -> Result<CipheredSecret, Error> {
+        self.block_on(self.server_connection_with_cipher_async())
+    }
 
-    // Add headers if provided
-    if let Some(custom_headers) = headers {
-        for (key, value) in custom_headers {
-            request = request.header(&key, value);
+    /// Async function to retrieve the secrets, and return the ciphertext along with the deciphered content.
+    ///
+    /// Secrets are always retrieved from the server, bypassing the cache which only holds the deciphered content.
+    /// Paginated tables are not supported, since each page carries a ciphertext of its own.
+    ///
+    /// # Returns
+    /// * A `Result<CipheredSecret, Error>` containing the ciphertext and the deciphered content.
+    pub async fn server_connection_with_cipher_async(&self) -> Result<CipheredSecret, Error> {
+        let config = &self.config;
+        if !config.put_secret.is_empty() || !config.delete_secret.is_empty() {
+            return Err(Error::Config(
+                "Only retrieved secrets carry a ciphertext, use server_connection to store or delete them".to_string()
+            ));
+        }
+        if !config.get_table.is_empty() && config.page_size > 0 {
+            return Err(Error::Config(
+                "Paginated tables can't be retrieved with their ciphertext, unset page_size".to_string()
+            ));
         }
+        let request = create_request_materials(config)?;
+        let table_name = request.params.get("table_name").unwrap_or_default().to_string();
+        let cipher = self.make_table_request_async(
+            Method::GET,
+            &request.url,
+            &request.table_name,
+            Some(request.headers),
+            Some(request.params)
+        ).await?;
+        let value = self.decrypt_response(cipher.clone(), &table_name)?;
+        Ok(CipheredSecret { cipher, value })
     }
 
-    // Make the request
-    match request.send() {
-        Ok(response) => {
-            match response.json::<Value>() {
-                Ok(json) => {
-                    // Try to get the value of "detail" if it exists
-                    if let Some(detail) = json.get("detail") {
-                        detail.clone()
-                    } else {
-                        // Return null if "detail" key is not present
-                        Value::Null
+    /// Checks that the server is reachable, and retrieves its status and version.
+    ///
+    /// Blocking wrapper around `health_async`, which must not be called from within an async runtime.
+    ///
+    /// # Returns
+    /// * A `Result<ServerInfo, Error>` containing the server status and version.
+    pub fn health(&self) -> Result<ServerInfo, Error> {
+        self.block_on(self.health_async())
+    }
+
+    /// Async function to check that the server is reachable, and retrieve its status and version.
+    ///
+    /// # Returns
+    /// * A `Result<ServerInfo, Error>` containing the server status and version.
+    pub async fn health_async(&self) -> Result<ServerInfo, Error> {
+        let response = self.send_request_async(
+            Method::GET,
+            &format!("{}health", &self.config.vault_server),
+            "",
+            Some(request_headers(&self.config)?),
+            None,
+            None,
+        ).await?;
+        match serde_json::from_value(response) {
+            Ok(info) => Ok(info),
+            Err(err) => Err(Error::Response(format!("Unexpected health response: {}", err))),
+        }
+    }
+
+    /// Retrieves and decrypts a single secret from a table.
+    ///
+    /// Blocking wrapper around `get_secret_async`, which must not be called from within an async runtime.
+    ///
+    /// # Arguments
+    /// * `table_name` - Name of the table to retrieve the secret from.
+    /// * `key` - Name of the secret.
+    ///
+    /// # Returns
+    /// * A `Result<Value, Error>` containing deciphered content.
+    pub fn get_secret(&self, table_name: &str, key: &str) -> Result<Value, Error> {
+        self.block_on(self.get_secret_async(table_name, key))
+    }
+
+    /// Async function to retrieve and decrypt a single secret from a table.
+    ///
+    /// Secrets are served from the cache for `cache_ttl` after being retrieved, when set.
+    ///
+    /// # Arguments
+    /// * `table_name` - Name of the table to retrieve the secret from.
+    /// * `key` - Name of the secret.
+    ///
+    /// # Returns
+    /// * A `Result<Value, Error>` containing deciphered content.
+    pub async fn get_secret_async(&self, table_name: &str, key: &str) -> Result<Value, Error> {
+        if let Some(value) = self.cached_secret(table_name, key) {
+            log::debug!("Serving {} from the cache", key);
+            return Ok(value);
+        }
+        let params = QueryParams::new().with("table_name", table_name).with("key", key);
+        let (json, metadata) = self.send_request_with_metadata_async(
+            Method::GET,
+            &self.config.endpoint(&self.config.path_get_secret),
+            table_name,
+            Some(request_headers(&self.config)?),
+            Some(params),
+            None,
+        ).await?;
+        let response = json.get("detail").cloned().unwrap_or(Value::Null);
+        let value = self.decrypt_detail(response, table_name)?;
+        if let Some(max_age) = metadata.max_age {
+            log::debug!("Server allows caching {} for {}s", key, max_age.as_secs());
+        }
+        self.cache_secret(table_name, key, &value, metadata.max_age);
+        Ok(value)
+    }
+
+    /// Encrypts a secret and stores it on the server.
+    ///
+    /// Blocking wrapper around `put_secret_async`, which must not be called from within an async runtime.
+    ///
+    /// # Arguments
+    /// * `table_name` - Name of the table to store the secret in.
+    /// * `key` - Name of the secret.
+    /// * `value` - Value of the secret.
+    ///
+    /// # Returns
+    /// * A `Result<Value, Error>` containing the `detail` field of the server response.
+    pub fn put_secret(&self, table_name: &str, key: &str, value: &Value) -> Result<Value, Error> {
+        self.block_on(self.put_secret_async(table_name, key, value))
+    }
+
+    /// Async function to encrypt a secret and store it on the server.
+    ///
+    /// The value is encrypted with the transit key, and sent as the `value` field of a JSON body
+    /// along with the `table_name` and the `key`.
+    ///
+    /// # Arguments
+    /// * `table_name` - Name of the table to store the secret in.
+    /// * `key` - Name of the secret.
+    /// * `value` - Value of the secret.
+    ///
+    /// # Returns
+    /// * A `Result<Value, Error>` containing the `detail` field of the server response.
+    pub async fn put_secret_async(&self, table_name: &str, key: &str, value: &Value) -> Result<Value, Error> {
+        let ciphertext = decipher::transit_encrypt(
+            self.config.apikey_for(table_name),
+            value,
+            &self.config.transit_options(),
+            self.config.aad(table_name).as_deref(),
+        )?;
+        let body = serde_json::json!({
+            "table_name": table_name,
+            "key": key,
+            "value": ciphertext,
+        });
+        let json = self.send_request_async(
+            Method::POST,
+            &self.config.endpoint(&self.config.path_put_secret),
+            table_name,
+            Some(request_headers(&self.config)?),
+            None,
+            Some(&body),
+        ).await?;
+        // Invalidate the cached copy, so the next read reflects the new value
+        self.evict_secret(table_name, key);
+        Ok(json.get("detail").cloned().unwrap_or(Value::Null))
+    }
+
+    /// Deletes a secret from the server.
+    ///
+    /// Blocking wrapper around `delete_secret_async`, which must not be called from within an async runtime.
+    ///
+    /// # Arguments
+    /// * `table_name` - Name of the table to delete the secret from.
+    /// * `key` - Name of the secret.
+    ///
+    /// # Returns
+    /// * A `Result<Value, Error>` containing the `detail` field of the server response.
+    pub fn delete_secret(&self, table_name: &str, key: &str) -> Result<Value, Error> {
+        self.block_on(self.delete_secret_async(table_name, key))
+    }
+
+    /// Async function to delete a secret from the server.
+    ///
+    /// # Arguments
+    /// * `table_name` - Name of the table to delete the secret from.
+    /// * `key` - Name of the secret.
+    ///
+    /// # Returns
+    /// * A `Result<Value, Error>` containing the `detail` field of the server response.
+    pub async fn delete_secret_async(&self, table_name: &str, key: &str) -> Result<Value, Error> {
+        let params = QueryParams::new().with("table_name", table_name).with("key", key);
+        let response = self.make_table_request_async(
+            Method::DELETE,
+            &self.config.endpoint(&self.config.path_delete_secret),
+            table_name,
+            Some(request_headers(&self.config)?),
+            Some(params)
+        ).await?;
+        self.evict_secret(table_name, key);
+        Ok(response)
+    }
+
+    /// Lists the names of the tables on the server.
+    ///
+    /// Blocking wrapper around `list_tables_async`, which must not be called from within an async runtime.
+    ///
+    /// # Returns
+    /// * A `Result<Vec<String>, Error>` containing the table names, empty if the server has none.
+    pub fn list_tables(&self) -> Result<Vec<String>, Error> {
+        self.block_on(self.list_tables_async())
+    }
+
+    /// Async function to list the names of the tables on the server.
+    ///
+    /// # Returns
+    /// * A `Result<Vec<String>, Error>` containing the table names, empty if the server has none.
+    pub async fn list_tables_async(&self) -> Result<Vec<String>, Error> {
+        let response = self.make_request_async(
+            Method::GET,
+            &self.config.endpoint(&self.config.path_list_tables),
+            Some(request_headers(&self.config)?),
+            None
+        ).await?;
+        names(response)
+    }
+
+    /// Streams the updates of a table pushed by the server as server-sent events, until stopped.
+    ///
+    /// Blocking wrapper around `watch_remote_async`, which must not be called from within an async runtime.
+    ///
+    /// # Arguments
+    /// * `table_name` - Name of the table to watch.
+    /// * `on_update` - Called with each decrypted update, or its decryption failure. Returns `false` to stop watching.
+    ///
+    /// # Returns
+    /// * A `Result<(), Error>` once stopped, or an error if the server rejects the request, eg: with a 401.
+    pub fn watch_remote<F: FnMut(Result<Value, Error>) -> bool>(&self, table_name: &str, on_update: F) -> Result<(), Error> {
+        self.block_on(self.watch_remote_async(table_name, on_update))
+    }
+
+    /// Async function to stream the updates of a table pushed by the server as server-sent events, until stopped.
+    ///
+    /// The `data` of each event is a response body like the other endpoints', `{"detail": "<ciphertext>"}`, or the
+    /// bare ciphertext. When the stream drops or the server is unavailable, the connection is re-established with
+    /// an exponential backoff of up to 30s, sending the `Last-Event-ID` so the server can replay the missed updates.
+    ///
+    /// # Arguments
+    /// * `table_name` - Name of the table to watch.
+    /// * `on_update` - Called with each decrypted update, or its decryption failure. Returns `false` to stop watching.
+    ///
+    /// # Returns
+    /// * A `Result<(), Error>` once stopped, or an error if the server rejects the request, eg: with a 401.
+    pub async fn watch_remote_async<F: FnMut(Result<Value, Error>) -> bool>(
+        &self,
+        table_name: &str,
+        mut on_update: F,
+    ) -> Result<(), Error> {
+        let mut url = match reqwest::Url::parse(&self.config.endpoint(&self.config.path_watch)) {
+            Ok(url) => url,
+            Err(err) => return Err(Error::Request(format!("Invalid URL {}: {}", self.config.path_watch, err))),
+        };
+        QueryParams::new().with("table_name", table_name).append_to(&mut url, self.config.percent_encode)?;
+        let mut attempt = 0;
+        let mut last_event_id: Option<String> = None;
+        loop {
+            let mut headers = request_headers(&self.config)?;
+            headers.insert("Accept".to_string(), "text/event-stream".to_string());
+            let request_id = match headers.iter().find(|(key, _)| key.eq_ignore_ascii_case(REQUEST_ID_HEADER)) {
+                Some((_, request_id)) => request_id.to_string(),
+                None if self.config.request_id.is_empty() => new_request_id(),
+                None => self.config.request_id.to_string(),
+            };
+            headers.insert(REQUEST_ID_HEADER.to_string(), request_id.to_string());
+            if let Some(event_id) = &last_event_id {
+                headers.insert("Last-Event-ID".to_string(), event_id.to_string());
+            }
+            let request = self.build_request(&Method::GET, url.clone(), table_name, &Some(headers), None);
+            let disconnected = match request.timeout(WATCH_CONNECTION_TIMEOUT).send().await {
+                Ok(mut response) if response.status().is_success() => {
+                    attempt = 0;
+                    log::info!("[{}] Watching {} for updates", request_id, table_name);
+                    let mut events = EventStream::default();
+                    loop {
+                        let chunk = match response.chunk().await {
+                            Ok(Some(chunk)) => chunk,
+                            Ok(None) => break "Server closed the stream".to_string(),
+                            Err(err) => break connection_error(url.as_str(), &err, WATCH_CONNECTION_TIMEOUT, "").to_string(),
+                        };
+                        for event in events.feed(&chunk) {
+                            if event.id.is_some() {
+                                last_event_id = event.id;
+                            }
+                            let detail = match serde_json::from_str::<Value>(&event.data) {
+                                Ok(Value::Object(mut body)) if body.contains_key("detail") => body.remove("detail").unwrap_or_default(),
+                                _ => Value::String(event.data),
+                            };
+                            if !on_update(self.decrypt_detail(detail, table_name)) {
+                                return Ok(());
+                            }
+                        }
                     }
                 }
-                Err(err) => {
-                    println!("Failed to parse response as JSON: {}", err);
-                    exit(1);
+                Ok(response) => {
+                    let status = response.status();
+                    let body = response.text().await.unwrap_or_default();
+                    let err = status_error(status, &body);
+                    // Only an unavailable server is worth reconnecting to, other statuses need a fix on either side
+                    if !(status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()) {
+                        return Err(traced(err, &request_id));
+                    }
+                    err.to_string()
+                }
+                Err(err) => connection_error(url.as_str(), &err, WATCH_CONNECTION_TIMEOUT, "").to_string(),
+            };
+            attempt += 1;
+            let delay = backoff_delay(self.config.retry_backoff_ms, attempt.min(16)).min(MAX_RECONNECT_DELAY);
+            log::warn!("[{}] {}, reconnecting in {}ms", request_id, disconnected, delay.as_millis());
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Lists the names of the secrets in a table.
+    ///
+    /// Blocking wrapper around `list_keys_async`, which must not be called from within an async runtime.
+    ///
+    /// # Arguments
+    /// * `table_name` - Name of the table.
+    ///
+    /// # Returns
+    /// * A `Result<Vec<String>, Error>` containing the secret names, empty if the table has none.
+    pub fn list_keys(&self, table_name: &str) -> Result<Vec<String>, Error> {
+        self.block_on(self.list_keys_async(table_name))
+    }
+
+    /// Async function to list the names of the secrets in a table.
+    ///
+    /// Servers without the `list-keys` endpoint respond with a 404, in which case the keys are taken
+    /// from the decrypted table instead.
+    ///
+    /// # Arguments
+    /// * `table_name` - Name of the table.
+    ///
+    /// # Returns
+    /// * A `Result<Vec<String>, Error>` containing the secret names, empty if the table has none.
+    pub async fn list_keys_async(&self, table_name: &str) -> Result<Vec<String>, Error> {
+        let result = self.make_table_request_async(
+            Method::GET,
+            &self.config.endpoint(&self.config.path_list_keys),
+            table_name,
+            Some(request_headers(&self.config)?),
+            Some(QueryParams::new().with("table_name", table_name))
+        ).await;
+        match result {
+            Ok(response) => names(response),
+            Err(Error::Status { code: 404, .. }) => {
+                log::debug!("No {} endpoint on the server, listing the keys of the table instead", self.config.path_list_keys);
+                match self.get_table_async(table_name).await? {
+                    Value::Object(table) => Ok(table.into_iter().map(|(key, _)| key).collect()),
+                    other => Err(Error::Response(format!("Expected an object of secrets, received: {}", other))),
+                }
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Retrieves a single secret and deserializes it into the caller's type.
+    ///
+    /// Blocking wrapper around `get_secret_as_async`, which must not be called from within an async runtime.
+    ///
+    /// # Arguments
+    /// * `table_name` - Name of the table to retrieve the secret from.
+    /// * `key` - Name of the secret.
+    ///
+    /// # Returns
+    /// * A `Result<T, Error>` containing the deserialized secret, or `Error::Deserialize` if it doesn't match `T`.
+    pub fn get_secret_as<T: DeserializeOwned>(&self, table_name: &str, key: &str) -> Result<T, Error> {
+        self.block_on(self.get_secret_as_async(table_name, key))
+    }
+
+    /// Async function to retrieve a single secret and deserialize it into the caller's type.
+    ///
+    /// # Arguments
+    /// * `table_name` - Name of the table to retrieve the secret from.
+    /// * `key` - Name of the secret.
+    ///
+    /// # Returns
+    /// * A `Result<T, Error>` containing the deserialized secret, or `Error::Deserialize` if it doesn't match `T`.
+    pub async fn get_secret_as_async<T: DeserializeOwned>(&self, table_name: &str, key: &str) -> Result<T, Error> {
+        let value = self.get_secret_async(table_name, key).await?;
+        serde_json::from_value(value).map_err(Error::Deserialize)
+    }
+
+    /// Retrieves and decrypts all the secrets in a table.
+    ///
+    /// Blocking wrapper around `get_table_async`, which must not be called from within an async runtime.
+    ///
+    /// # Arguments
+    /// * `table_name` - Name of the table to retrieve.
+    ///
+    /// # Returns
+    /// * A `Result<Value, Error>` containing deciphered content.
+    pub fn get_table(&self, table_name: &str) -> Result<Value, Error> {
+        self.block_on(self.get_table_async(table_name))
+    }
+
+    /// Async function to retrieve and decrypt all the secrets in a table.
+    ///
+    /// The table is retrieved page by page when `page_size` is set in the config, see `next_page`. Each page is
+    /// read like the pages of `get_table_iter`, so servers returning one ciphertext per row are accepted too,
+    /// with the rows that fail to decrypt returned as `{"error": "<reason>"}`.
+    ///
+    /// # Arguments
+    /// * `table_name` - Name of the table to retrieve.
+    ///
+    /// # Returns
+    /// * A `Result<Value, Error>` containing deciphered content.
+    pub async fn get_table_async(&self, table_name: &str) -> Result<Value, Error> {
+        let mut rows = serde_json::Map::new();
+        let mut seen = HashSet::new();
+        let mut page = Some("1".to_string());
+        while let Some(current) = page {
+            let table_page = self.table_page_async(table_name, &current, &mut seen).await?;
+            match table_page.encrypted {
+                true => rows.extend(self.decrypt_each(table_page.rows, table_name)),
+                false => rows.extend(table_page.rows),
+            }
+            page = table_page.next;
+        }
+        Ok(Value::Object(rows))
+    }
+
+    /// Retrieves and decrypts all the secrets in a table, and writes them to a file as JSON.
+    ///
+    /// Blocking wrapper around `get_table_to_file_async`, which must not be called from within an async runtime.
+    ///
+    /// # Arguments
+    /// * `table_name` - Name of the table to retrieve.
+    /// * `path` - Destination file path.
+    ///
+    /// # Returns
+    /// * A `Result<u64, Error>` containing the number of bytes written.
+    pub fn get_table_to_file(&self, table_name: &str, path: &Path) -> Result<u64, Error> {
+        self.block_on(self.get_table_to_file_async(table_name, path))
+    }
+
+    /// Async function to retrieve and decrypt all the secrets in a table, and write them to a file as JSON.
+    ///
+    /// The decrypted table is serialized straight to the file instead of into an intermediate string,
+    /// logging the progress at info level. The content is written to a temporary file readable by the owner only,
+    /// created exclusively under a random name and renamed once complete, so an interrupted download never leaves
+    /// a truncated backup behind.
+    ///
+    /// # Arguments
+    /// * `table_name` - Name of the table to retrieve.
+    /// * `path` - Destination file path.
+    ///
+    /// # Returns
+    /// * A `Result<u64, Error>` containing the number of bytes written.
+    pub async fn get_table_to_file_async(&self, table_name: &str, path: &Path) -> Result<u64, Error> {
+        let table = self.get_table_async(table_name).await?;
+        let result = atomic::write_atomic(path, Some(0o600), |file| {
+            let mut writer = ProgressWriter { inner: BufWriter::new(file), written: 0, logged: 0 };
+            serde_json::to_writer(&mut writer, &table)?;
+            writer.flush()?;
+            Ok(writer.written)
+        });
+        match result {
+            Ok(written) => {
+                log::info!("{} bytes written to {}", written, path.display());
+                Ok(written)
+            }
+            Err(err) => Err(Error::Io(format!("Failed to write {}: {}", path.display(), err))),
+        }
+    }
+
+    /// Retrieves the secrets in a table as an iterator of `(key, value)` rows, decrypted as they are consumed.
+    ///
+    /// Unlike `get_table`, only one page of the table is held at a time when `page_size` is set in the config,
+    /// the next page being requested once the rows of the current one are consumed. Servers returning one
+    /// ciphertext per row have each row decrypted on demand, and a row failing to decrypt doesn't end the iteration.
+    /// A failed request is yielded as the last item. Must not be consumed from within an async runtime.
+    ///
+    /// # Arguments
+    /// * `table_name` - Name of the table to retrieve.
+    ///
+    /// # Returns
+    /// * An iterator of `Result<(String, Value), Error>` containing each key with its deciphered content.
+    pub fn get_table_iter(&self, table_name: &str) -> impl Iterator<Item = Result<(String, Value), Error>> + '_ {
+        TableRows {
+            vault: self,
+            table_name: table_name.to_string(),
+            rows: serde_json::Map::new().into_iter(),
+            encrypted: false,
+            seen: HashSet::new(),
+            next: Some("1".to_string()),
+        }
+    }
+
+    /// Retrieves one page of a table for `get_table` and `get_table_iter`, or the whole table when `page_size`
+    /// is not set.
+    ///
+    /// # Arguments
+    /// * `table_name` - Name of the table to retrieve.
+    /// * `page` - Page to request, ignored without `page_size`.
+    /// * `seen` - Keys received in the previous pages, updated with those of this page.
+    ///
+    /// # Returns
+    /// * A `Result<TablePage, Error>` containing the rows of the page not received before, decrypted unless
+    ///   encrypted one by one.
+    async fn table_page_async(&self, table_name: &str, page: &str, seen: &mut HashSet<String>) -> Result<TablePage, Error> {
+        let page_size = self.config.page_size as usize;
+        let mut params = QueryParams::new().with("table_name", table_name);
+        if page_size > 0 {
+            params.push("page", page);
+            params.push("limit", &page_size.to_string());
+        }
+        let envelope = self.send_request_async(
+            Method::GET,
+            &self.config.endpoint(&self.config.path_get_table),
+            table_name,
+            Some(request_headers(&self.config)?),
+            Some(params),
+            None,
+        ).await?;
+        let detail = envelope.get("detail").cloned().unwrap_or(Value::Null);
+        let per_row = detail.is_object() && server_error(&detail).is_none();
+        let (mut rows, encrypted) = match detail {
+            Value::Object(rows) if per_row => (rows, true),
+            detail => match self.decrypt_detail(detail, table_name)? {
+                Value::Object(rows) => (rows, false),
+                other => return Err(Error::Response(format!(
+                    "Expected an object of rows in page {}, received: {}", page, other
+                ))),
+            },
+        };
+        let received = rows.len();
+        log::debug!("Received {} rows in page {}", received, page);
+        // Rows are only ever yielded once, like the keys of the object accumulated by `get_table`
+        rows.retain(|key, _| seen.insert(key.clone()));
+        let repeated = rows.is_empty();
+        let next = if page_size > 0 { next_page(&envelope, page, received, page_size, repeated) } else { None };
+        Ok(TablePage { rows, encrypted, next })
+    }
+
+    /// Decrypts the `detail` field of a retrieval, one key at a time for `get-secrets`.
+    ///
+    /// # Arguments
+    /// * `response` - Value of the `detail` field.
+    /// * `table_name` - Name of the table the secrets were retrieved from.
+    ///
+    /// # Returns
+    /// * A `Result<Value, Error>` containing deciphered content.
+    fn decrypt_response(&self, response: Value, table_name: &str) -> Result<Value, Error> {
+        if !self.config.get_secrets.is_empty() && server_error(&response).is_none() {
+            if let Value::Object(ciphers) = response {
+                return Ok(Value::Object(self.decrypt_each(ciphers, table_name)));
+            }
+        }
+        self.decrypt_detail(response, table_name)
+    }
+
+    /// Decrypts an object of ciphertexts returned by `get-secrets`, one key at a time.
+    ///
+    /// Keys that fail to decrypt don't abort the others, they are returned as `{"error": "<reason>"}` instead.
+    ///
+    /// # Arguments
+    /// * `ciphers` - Object mapping each key to its ciphertext.
+    /// * `table_name` - Name of the table the secrets were retrieved from.
+    ///
+    /// # Returns
+    /// * A `serde_json::Map` mapping each key to its deciphered content or error marker.
+    fn decrypt_each(&self, ciphers: serde_json::Map<String, Value>, table_name: &str) -> serde_json::Map<String, Value> {
+        let mut secrets = serde_json::Map::new();
+        for (key, cipher) in ciphers {
+            let value = match self.decrypt_detail(cipher, table_name) {
+                Ok(value) => value,
+                Err(err) => serde_json::json!({ "error": err.to_string() }),
+            };
+            secrets.insert(key, value);
+        }
+        secrets
+    }
+
+    /// Decrypts the `detail` field of the server response.
+    ///
+    /// An array of ciphertexts is decrypted element by element, in order. Elements that fail are replaced
+    /// with `{"error": "<reason>", "index": <position>}` instead of failing the whole array.
+    ///
+    /// # Arguments
+    /// * `response` - Value of the `detail` field.
+    /// * `table_name` - Name of the table the secret was retrieved from, used as the AAD when enabled.
+    ///
+    /// # Returns
+    /// * A `Result<Value, Error>` containing deciphered content.
+    fn decrypt_detail(&self, response: Value, table_name: &str) -> Result<Value, Error> {
+        // Check if the result is the expected "detail" field, or handle accordingly
+        match response {
+            Value::Null => {
+                Err(Error::Response("No 'detail' key found in the response.".to_string()))
+            }
+            Value::String(cipher_text) => {
+                let started = Instant::now();
+                let result = decipher::transit_decrypt_verbose(
+                    self.config.apikey_for(table_name),
+                    &cipher_text,
+                    &self.config.transit_options(),
+                    self.config.aad(table_name).as_deref(),
+                );
+                self.metrics.decrypt_completed(result.is_ok(), started.elapsed());
+                let result = result?;
+                log::debug!("Decrypted with epoch bucket {} and key length {}", result.epoch_bucket, result.key_length);
+                let decrypted = result.value;
+                log::debug!("Decrypted payload of {} bytes", decrypted.to_string().len());
+                Ok(decrypted)
+            }
+            Value::Array(ciphers) => {
+                // Elements that fail to decrypt don't abort the others, and keep their position in the array
+                let decrypted = ciphers
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, cipher)| match self.decrypt_detail(cipher, table_name) {
+                        Ok(value) => value,
+                        Err(err) => {
+                            log::warn!("Failed to decrypt element {} of the array: {}", index, err);
+                            serde_json::json!({ "error": err.to_string(), "index": index })
+                        }
+                    })
+                    .collect();
+                Ok(Value::Array(decrypted))
+            }
+            Value::Object(_) => match server_error(&response) {
+                Some(message) => Err(Error::Response(format!("Server returned an error: {}", message))),
+                None => Err(Error::Response(format!("Detail is an object: {}", response))),
+            },
+            _ => {
+                Err(Error::Response(format!("Unexpected value returned: {}", response)))
+            }
+        }
+    }
+
+    /// Async function to retrieve each of the comma separated `get_secrets` keys with its own request, concurrently.
+    ///
+    /// Up to `parallel_limit` requests are in flight at once, and a failure for one key is recorded against
+    /// that key and doesn't abort the others.
+    ///
+    /// # Returns
+    /// * A `Result` containing a `HashMap` of each key to its deciphered content or error.
+    pub async fn get_secrets_parallel_async(&self) -> Result<HashMap<String, Result<Value, Error>>, Error> {
+        let config = &self.config;
+        if config.table_name.is_empty() {
+            return Err(Error::Config("Table name is mandatory to retrieve the secret".to_string()));
+        }
+        let keys = normalize_keys(&config.get_secrets)?;
+        let results = futures_util::stream::iter(keys)
+            .map(|key| async move {
+                let result = self.get_secret_async(&config.table_name, &key).await;
+                (key, result)
+            })
+            .buffer_unordered(config.parallel_limit.max(1))
+            .collect()
+            .await;
+        Ok(results)
+    }
+
+    /// Retrieves each of the comma separated `get_secrets` keys with its own request, concurrently.
+    ///
+    /// Blocking wrapper around `get_secrets_parallel_async`, which must not be called from within an async runtime.
+    ///
+    /// # Returns
+    /// * A `Result` containing a `HashMap` of each key to its deciphered content or error.
+    pub fn get_secrets_parallel(&self) -> Result<HashMap<String, Result<Value, Error>>, Error> {
+        self.block_on(self.get_secrets_parallel_async())
+    }
+
+    /// Function to make a request to the server.
+    ///
+    /// Blocking wrapper around `make_request_async`, which must not be called from within an async runtime.
+    ///
+    /// # Arguments
+    /// * `method` - HTTP method, eg: `GET` or `DELETE`.
+    /// * `server_url` - Server URL.
+    /// * `headers` - Headers to send, in addition to the ones set by the client's `Auth`.
+    /// * `params` - Query parameters.
+    ///
+    /// # Returns
+    /// * A `Result<Value, Error>` containing the `detail` field of the server response.
+    pub fn make_request(
+        &self,
+        method: Method,
+        server_url: &str,
+        headers: Option<HashMap<String, String>>,
+        params: Option<QueryParams>,
+    ) -> Result<Value, Error> {
+        self.block_on(self.make_request_async(method, server_url, headers, params))
+    }
+
+    /// Async function to make a request to the server.
+    ///
+    /// The request is authenticated by the client's `Auth` with the global apikey.
+    ///
+    /// # Arguments
+    /// * `method` - HTTP method, eg: `GET` or `DELETE`.
+    /// * `server_url` - Server URL.
+    /// * `headers` - Headers to send, in addition to the ones set by the client's `Auth`.
+    /// * `params` - Query parameters.
+    ///
+    /// # Returns
+    /// * A `Result<Value, Error>` containing the `detail` field of the server response.
+    pub async fn make_request_async(
+        &self,
+        method: Method,
+        server_url: &str,
+        headers: Option<HashMap<String, String>>,
+        params: Option<QueryParams>,
+    ) -> Result<Value, Error> {
+        self.make_table_request_async(method, server_url, "", headers, params).await
+    }
+
+    /// Makes a request to the server, returning the response metadata along with the `detail` field.
+    ///
+    /// Blocking wrapper around `make_request_with_metadata_async`, which must not be called from within an async runtime.
+    ///
+    /// # Arguments
+    /// * `method` - HTTP method, eg: `GET` or `DELETE`.
+    /// * `server_url` - Server URL.
+    /// * `headers` - Headers to send, in addition to the ones set by the client's `Auth`.
+    /// * `params` - Query parameters.
+    ///
+    /// # Returns
+    /// * A `Result` containing the `detail` field of the server response and its `ResponseMetadata`.
+    pub fn make_request_with_metadata(
+        &self,
+        method: Method,
+        server_url: &str,
+        headers: Option<HashMap<String, String>>,
+        params: Option<QueryParams>,
+    ) -> Result<(Value, ResponseMetadata), Error> {
+        self.block_on(self.make_request_with_metadata_async(method, server_url, headers, params))
+    }
+
+    /// Async function to make a request to the server, returning the response metadata along with the `detail` field.
+    ///
+    /// The request is authenticated by the client's `Auth` with the global apikey.
+    ///
+    /// # Arguments
+    /// * `method` - HTTP method, eg: `GET` or `DELETE`.
+    /// * `server_url` - Server URL.
+    /// * `headers` - Headers to send, in addition to the ones set by the client's `Auth`.
+    /// * `params` - Query parameters.
+    ///
+    /// # Returns
+    /// * A `Result` containing the `detail` field of the server response and its `ResponseMetadata`.
+    pub async fn make_request_with_metadata_async(
+        &self,
+        method: Method,
+        server_url: &str,
+        headers: Option<HashMap<String, String>>,
+        params: Option<QueryParams>,
+    ) -> Result<(Value, ResponseMetadata), Error> {
+        let (json, metadata) = self.send_request_with_metadata_async(method, server_url, "", headers, params, None).await?;
+        Ok((json.get("detail").cloned().unwrap_or(Value::Null), metadata))
+    }
+
+    /// Async function to make a request for a table, authenticated with the table's apikey.
+    ///
+    /// # Arguments
+    /// * `method` - HTTP method, eg: `GET` or `DELETE`.
+    /// * `server_url` - Server URL.
+    /// * `table_name` - Name of the table the request is for, to select its apikey. Empty for the global apikey.
+    /// * `headers` - Headers to send, in addition to the ones set by the client's `Auth`.
+    /// * `params` - Query parameters.
+    ///
+    /// # Returns
+    /// * A `Result<Value, Error>` containing the `detail` field of the server response.
+    async fn make_table_request_async(
+        &self,
+        method: Method,
+        server_url: &str,
+        table_name: &str,
+        headers: Option<HashMap<String, String>>,
+        params: Option<QueryParams>,
+    ) -> Result<Value, Error> {
+        let json = self.send_request_async(method, server_url, table_name, headers, params, None).await?;
+        // Try to get the value of "detail" if it exists, return null otherwise
+        Ok(json.get("detail").cloned().unwrap_or(Value::Null))
+    }
+
+    /// Prepares a request with the headers and the body, authenticated by the client's `Auth`.
+    ///
+    /// # Arguments
+    /// * `method` - HTTP method, eg: `GET` or `POST`.
+    /// * `url` - Full URL including the query parameters.
+    /// * `table_name` - Name of the table the request is for, to select its apikey. Empty for the global apikey.
+    /// * `headers` - Headers to send.
+    /// * `body` - JSON body to send with the request.
+    ///
+    /// # Returns
+    /// * A `reqwest::RequestBuilder` ready to be sent.
+    fn build_request(
+        &self,
+        method: &Method,
+        url: reqwest::Url,
+        table_name: &str,
+        headers: &Option<HashMap<String, String>>,
+        body: Option<&Value>,
+    ) -> reqwest::RequestBuilder {
+        let mut request = self.http.request(method.clone(), url.clone());
+        if let Some(json) = body {
+            request = request.json(json);
+        }
+
+        // Add headers if provided
+        if let Some(custom_headers) = headers {
+            for (key, value) in custom_headers {
+                request = request.header(key, value);
+            }
+        }
+        let context = AuthContext {
+            method,
+            url: &url,
+            apikey: self.config.apikey_for(table_name),
+        };
+        self.auth.authenticate(request, &context)
+    }
+
+    /// Describes the request `server_connection` would send for the operation in the config, without sending it.
+    ///
+    /// # Returns
+    /// * A `Result<Value, Error>` containing the method, the final URL with query parameters, and the header names.
+    pub fn describe(&self) -> Result<Value, Error> {
+        let config = &self.config;
+        let (method, path, params) = if !config.delete_secret.is_empty() {
+            let params = QueryParams::new()
+                .with("table_name", &config.table_name)
+                .with("key", &config.delete_secret);
+            (Method::DELETE, &config.path_delete_secret, params)
+        } else if config.list_tables {
+            (Method::GET, &config.path_list_tables, QueryParams::new())
+        } else if config.list_keys {
+            (Method::GET, &config.path_list_keys, QueryParams::new().with("table_name", &config.table_name))
+        } else if !config.put_secret.is_empty() {
+            (Method::POST, &config.path_put_secret, QueryParams::new())
+        } else {
+            return self.describe_request(&create_request_materials(config)?);
+        };
+        self.describe_request(&RequestMaterials {
+            method,
+            url: config.endpoint(path),
+            params,
+            headers: request_headers(config)?,
+            table_name: config.table_name.to_string(),
+        })
+    }
+
+    /// Describes a request without sending it, masking the header values.
+    ///
+    /// # Arguments
+    /// * `request` - Request materials to describe.
+    ///
+    /// # Returns
+    /// * A `Result<Value, Error>` containing the method, the final URL with query parameters, and the header names.
+    fn describe_request(&self, request: &RequestMaterials) -> Result<Value, Error> {
+        let mut url = match reqwest::Url::parse(&request.url) {
+            Ok(url) => url,
+            Err(err) => return Err(Error::Request(format!("Invalid URL {}: {}", request.url, err))),
+        };
+        if !request.params.is_empty() {
+            request.params.append_to(&mut url, self.config.percent_encode)?;
+        }
+        let headers = Some(request.headers.clone());
+        let built = match self.build_request(&request.method, url, &request.table_name, &headers, None).build() {
+            Ok(built) => built,
+            Err(err) => return Err(Error::Request(format!("Failed to build the request: {}", err))),
+        };
+        // Header values carry the apikey, so only the names are shown
+        let headers: serde_json::Map<String, Value> = built.headers()
+            .keys()
+            .map(|name| (name.to_string(), Value::String("***".to_string())))
+            .collect();
+        Ok(serde_json::json!({
+            "method": built.method().as_str(),
+            "url": built.url().to_string(),
+            "headers": headers,
+        }))
+    }
+
+    /// Async function to make a request to the server, with retries on transient failures.
+    ///
+    /// # Arguments
+    /// * `method` - HTTP method, eg: `GET` or `POST`.
+    /// * `server_url` - Server URL.
+    /// * `table_name` - Name of the table the request is for, to select its apikey. Empty for the global apikey.
+    /// * `headers` - Headers to send, in addition to the ones set by the client's `Auth`.
+    /// * `params` - Query parameters.
+    /// * `body` - JSON body to send with the request.
+    ///
+    /// # Returns
+    /// * A `Result<Value, Error>` containing the full JSON body of the server response.
+    async fn send_request_async(
+        &self,
+        method: Method,
+        server_url: &str,
+        table_name: &str,
+        headers: Option<HashMap<String, String>>,
+        params: Option<QueryParams>,
+        body: Option<&Value>,
+    ) -> Result<Value, Error> {
+        let (json, _) = self.send_request_with_metadata_async(method, server_url, table_name, headers, params, body).await?;
+        Ok(json)
+    }
+
+    /// Async function to make a request to the server, with retries on transient failures, keeping the response metadata.
+    ///
+    /// # Arguments
+    /// * `method` - HTTP method, eg: `GET` or `POST`.
+    /// * `server_url` - Server URL.
+    /// * `table_name` - Name of the table the request is for, to select its apikey. Empty for the global apikey.
+    /// * `headers` - Headers to send, in addition to the ones set by the client's `Auth`.
+    /// * `params` - Query parameters.
+    /// * `body` - JSON body to send with the request.
+    ///
+    /// # Returns
+    /// * A `Result` containing the full JSON body of the server response and its `ResponseMetadata`.
+    async fn send_request_with_metadata_async(
+        &self,
+        method: Method,
+        server_url: &str,
+        table_name: &str,
+        headers: Option<HashMap<String, String>>,
+        params: Option<QueryParams>,
+        body: Option<&Value>,
+    ) -> Result<(Value, ResponseMetadata), Error> {
+        // All attempts share the request ID, taken from an extra X-Request-ID header or --request-id when set
+        let mut headers = headers.unwrap_or_default();
+        let request_id = match headers.iter().find(|(key, _)| key.eq_ignore_ascii_case(REQUEST_ID_HEADER)) {
+            Some((_, request_id)) => request_id.to_string(),
+            None => {
+                let request_id = if self.config.request_id.is_empty() {
+                    new_request_id()
+                } else {
+                    self.config.request_id.to_string()
+                };
+                headers.insert(REQUEST_ID_HEADER.to_string(), request_id.to_string());
+                request_id
+            }
+        };
+        let headers = Some(headers);
+        log::debug!("[{}] {} {}", request_id, method, server_url);
+
+        // The same endpoint on the fallback server, when the request targets the primary
+        let mut targets = vec![server_url.to_string()];
+        if let Some(fallback) = &self.config.fallback_server {
+            if let Some(path) = server_url.strip_prefix(self.config.vault_server.as_str()) {
+                targets.push(format!("{}{}", fallback, path));
+            }
+        }
+        let params = params.filter(|query_params| !query_params.is_empty());
+
+        // Spread out the requests of a fleet of clients, which all re-fetch at the same epoch bucket rollover
+        if self.config.request_jitter_ms > 0 {
+            let jitter = random_millis(self.config.request_jitter_ms);
+            log::debug!("[{}] Delaying the request by {}ms", request_id, jitter);
+            tokio::time::sleep(Duration::from_millis(jitter)).await;
+        }
+
+        let mut index = 0;
+        let (server_url, result) = loop {
+            let target = &targets[index];
+            // Build the URL with parameters if provided
+            let mut url = match reqwest::Url::parse(target) {
+                Ok(url) => url,
+                Err(err) => return Err(Error::Request(format!("Invalid URL {}: {}", target, err))),
+            };
+            if let Some(query_params) = &params {
+                query_params.append_to(&mut url, self.config.percent_encode)?;
+            }
+            let result = self.send_with_retries_async(&method, target, url, table_name, &headers, body, &request_id).await;
+            let failed = match &result {
+                Ok(response) => response.status().is_server_error(),
+                Err(err) => err.is_connect() || err.is_timeout(),
+            };
+            if !failed || index + 1 == targets.len() {
+                break (target.as_str(), result);
+            }
+            index += 1;
+            log::warn!("[{}] Request to {} failed, falling back to {}", request_id, target, targets[index]);
+        };
+        if targets.len() > 1 && result.is_ok() {
+            log::info!("[{}] Request served by {}", request_id, server_url);
+        }
+
+        // Process the final attempt
+        let timeout = self.config.timeout;
+        let processed = match result {
+            Ok(response) if !response.status().is_success() => {
+                let status = response.status();
+                match response.text().await {
+                    Ok(body) => Err(status_error(status, &body)),
+                    Err(_) => Err(status_error(status, "")),
+                }
+            }
+            Ok(response) => {
+                let headers = response.headers().clone();
+                match response.bytes().await {
+                    Ok(body) => Ok((parse_body(&headers, &body)?, ResponseMetadata::from_headers(&headers))),
+                    Err(err) if err.is_timeout() => {
+                        Err(connection_error(server_url, &err, timeout, &request_id).into())
+                    }
+                    Err(err) => {
+                        Err(Error::Request(format!("Failed to read the response: {}", err)))
+                    }
                 }
             }
+            Err(err) => Err(connection_error(server_url, &err, timeout, &request_id).into()),
+        };
+        processed.map_err(|err| traced(err, &request_id))
+    }
+
+    /// Sends a request to a single server, retrying on connection errors, 429 and transient 5xx responses.
+    ///
+    /// # Arguments
+    /// * `method` - HTTP method, eg: `GET` or `POST`.
+    /// * `server_url` - Server URL without the query parameters, reported to the metrics hook.
+    /// * `url` - Full URL including the query parameters.
+    /// * `table_name` - Name of the table the request is for, to select its apikey. Empty for the global apikey.
+    /// * `headers` - Headers to send, in addition to the ones set by the client's `Auth`.
+    /// * `body` - JSON body to send with the request.
+    /// * `request_id` - Value of the `X-Request-ID` header, logged with each attempt.
+    ///
+    /// # Returns
+    /// * The `reqwest::Result` of the final attempt.
+    #[allow(clippy::too_many_arguments)]
+    async fn send_with_retries_async(
+        &self,
+        method: &Method,
+        server_url: &str,
+        url: reqwest::Url,
+        table_name: &str,
+        headers: &Option<HashMap<String, String>>,
+        body: Option<&Value>,
+        request_id: &str,
+    ) -> reqwest::Result<reqwest::Response> {
+        let mut attempt = 0;
+        loop {
+            // Prepare and authenticate each attempt afresh, for time based signatures
+            let request = self.build_request(method, url.clone(), table_name, headers, body);
+
+            self.metrics.request_started(method.as_str(), server_url);
+            let started = Instant::now();
+            let result = request.send().await;
+            let status = result.as_ref().ok().map(|response| response.status().as_u16());
+            self.metrics.request_completed(method.as_str(), server_url, status, started.elapsed());
+            let transient = match &result {
+                Ok(response) => matches!(
+                    response.status(),
+                    StatusCode::TOO_MANY_REQUESTS
+                        | StatusCode::BAD_GATEWAY
+                        | StatusCode::SERVICE_UNAVAILABLE
+                        | StatusCode::GATEWAY_TIMEOUT
+                ),
+                Err(err) => err.is_connect(),
+            };
+            if !transient || attempt >= self.config.retries {
+                return result;
+            }
+            attempt += 1;
+            // Honor the server's Retry-After hint when present, fallback to exponential backoff
+            let delay = match &result {
+                Ok(response) => retry_after(response.headers()),
+                Err(_) => None,
+            }.unwrap_or_else(|| backoff_delay(self.config.retry_backoff_ms, attempt));
+            log::debug!(
+                "[{}] Attempt {} of {} failed, retrying in {}ms", request_id, attempt, self.config.retries + 1, delay.as_millis()
+            );
+            self.metrics.retry(server_url, attempt, delay);
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn keys(list: &[&str]) -> Vec<String> {
+        list.iter().map(|key| key.to_string()).collect()
+    }
+
+    #[test]
+    fn normalize_keys_trims_each_key() {
+        assert_eq!(normalize_keys(" a , b\t,\nc ").unwrap(), keys(&["a", "b", "c"]));
+        assert_eq!(normalize_keys("db.password").unwrap(), keys(&["db.password"]));
+    }
+
+    #[test]
+    fn normalize_keys_drops_duplicates_in_the_original_order() {
+        assert_eq!(normalize_keys("b,a,b, a,c").unwrap(), keys(&["b", "a", "c"]));
+    }
+
+    #[test]
+    fn normalize_keys_drops_empty_entries() {
+        assert_eq!(normalize_keys(",a,, ,b,").unwrap(), keys(&["a", "b"]));
+    }
+
+    #[test]
+    fn normalize_keys_rejects_a_list_without_keys() {
+        for list in ["", ",", " , ,", "\t"] {
+            match normalize_keys(list) {
+                Err(Error::Config(message)) => assert_eq!(message, format!("No secret keys found in: '{}'", list)),
+                other => panic!("{:?} resolved to {:?}", list, other),
+            }
+        }
+    }
+
+    #[test]
+    fn normalize_keys_rejects_invalid_characters() {
+        for list in ["a,b c", "a&b=c", "a,key/..", "ключ"] {
+            assert!(matches!(normalize_keys(list), Err(Error::Config(message)) if message.starts_with("Invalid secret key: ")));
         }
-        Err(err) => {
-            println!("Failed to fetch data from {}: {}", server_url, err);
-            exit(1);
+    }
+
+    #[test]
+    fn missing_keys_are_reported_in_the_requested_order() {
+        let secrets = json!({ "b": 1, "d": null });
+        assert_eq!(missing_keys(&keys(&["c", "b", "a", "d"]), &secrets), keys(&["c", "a"]));
+        assert!(missing_keys(&keys(&["b", "d"]), &secrets).is_empty());
+    }
+
+    #[test]
+    fn every_key_is_missing_from_a_non_object() {
+        for secrets in [json!(null), json!("a"), json!(["a", "b"])] {
+            assert_eq!(missing_keys(&keys(&["a", "b"]), &secrets), keys(&["a", "b"]));
         }
     }
 }
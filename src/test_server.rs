@@ -0,0 +1,190 @@
+use crate::config::{parse_vault_server, Config};
+use crate::decipher::{self, TransitOptions};
+use reqwest::Url;
+use serde_json::Value;
+use std::future::Future;
+use wiremock::matchers::{method, path, query_param};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+/// Drives a future to completion on the runtime, from another thread when called within an async test, where
+/// the runtime can't be blocked on.
+///
+/// # Arguments
+/// * `runtime` - Runtime of the test server.
+/// * `future` - Future to wait on.
+///
+/// # Returns
+/// * The output of the future.
+fn block_on<F: Future + Send>(runtime: &tokio::runtime::Runtime, future: F) -> F::Output
+where
+    F::Output: Send,
+{
+    if tokio::runtime::Handle::try_current().is_err() {
+        return runtime.block_on(future);
+    }
+    match std::thread::scope(|scope| scope.spawn(|| runtime.block_on(future)).join()) {
+        Ok(output) => output,
+        Err(panic) => std::panic::resume_unwind(panic),
+    }
+}
+
+/// Mock VaultAPI server serving canned `{"detail": ...}` responses, to exercise the fetch and decrypt path
+/// in integration tests without a live server.
+///
+/// The server listens on a random local port until dropped. It can be used from synchronous tests,
+/// as it runs on its own thread, apart from the runtime of the `VaultClient`, and from async tests.
+pub struct TestServer {
+    /// Always set, only taken to be shut down when dropped.
+    runtime: Option<tokio::runtime::Runtime>,
+    server: MockServer,
+    apikey: String,
+    options: TransitOptions,
+}
+
+impl TestServer {
+    /// Starts a server encrypting its secrets with the default transit settings.
+    ///
+    /// # Arguments
+    /// * `apikey` - APIkey to derive the transit key from.
+    ///
+    /// # Returns
+    /// * A started `TestServer`.
+    pub fn start(apikey: &str) -> Self {
+        Self::with_options(apikey, TransitOptions::default())
+    }
+
+    /// Starts a server encrypting its secrets with the given transit settings.
+    ///
+    /// # Arguments
+    /// * `apikey` - APIkey to derive the transit key from.
+    /// * `options` - Transit settings, which the config of the client has to match.
+    ///
+    /// # Returns
+    /// * A started `TestServer`.
+    pub fn with_options(apikey: &str, options: TransitOptions) -> Self {
+        let runtime = match tokio::runtime::Builder::new_current_thread().enable_all().build() {
+            Ok(runtime) => runtime,
+            Err(err) => panic!("Failed to build the runtime of the test server: {}", err),
+        };
+        let server = block_on(&runtime, MockServer::start());
+        TestServer { runtime: Some(runtime), server, apikey: apikey.to_string(), options }
+    }
+
+    /// Drives a future to completion on the runtime of the server, see `block_on`.
+    fn block_on<F: Future + Send>(&self, future: F) -> F::Output
+    where
+        F::Output: Send,
+    {
+        match &self.runtime {
+            Some(runtime) => block_on(runtime, future),
+            None => unreachable!("The runtime is only taken when dropped"),
+        }
+    }
+
+    /// Base URL of the server, with a trailing slash.
+    pub fn url(&self) -> Url {
+        match parse_vault_server(&self.server.uri()) {
+            Ok(url) => url,
+            Err(err) => panic!("Invalid test server URL {}: {}", self.server.uri(), err),
+        }
+    }
+
+    /// Creates a config pointing at the server, with the default settings and the server's apikey.
+    pub fn config(&self) -> Config {
+        Config::new(self.url(), self.apikey.clone())
+    }
+
+    /// Encrypts a payload with the server's apikey and transit settings.
+    ///
+    /// # Arguments
+    /// * `plaintext` - JSON value to encrypt.
+    ///
+    /// # Returns
+    /// * A `String` containing the base64-encoded ciphertext.
+    pub fn encrypt(&self, plaintext: &Value) -> String {
+        match decipher::transit_encrypt(&self.apikey, plaintext, &self.options, None) {
+            Ok(ciphertext) => ciphertext,
+            Err(err) => panic!("Failed to encrypt the test secret: {}", err),
+        }
+    }
+
+    /// Serves `{"detail": <detail>}` to every `GET` request for the path, regardless of the query parameters.
+    ///
+    /// # Arguments
+    /// * `endpoint` - Path relative to the server URL, eg: `get-secret`.
+    /// * `detail` - Value of the `detail` field, eg: a ciphertext from `encrypt`, or `null`.
+    pub fn serve_detail(&self, endpoint: &str, detail: Value) {
+        self.serve_response(endpoint, 200, serde_json::json!({ "detail": detail }));
+    }
+
+    /// Serves a payload encrypted with `encrypt` as the `detail` of every `GET` request for the path.
+    ///
+    /// # Arguments
+    /// * `endpoint` - Path relative to the server URL, eg: `get-secret`.
+    /// * `plaintext` - JSON value to encrypt.
+    pub fn serve_secret(&self, endpoint: &str, plaintext: &Value) {
+        self.serve_detail(endpoint, Value::String(self.encrypt(plaintext)));
+    }
+
+    /// Serves a payload encrypted with `encrypt` only to the `GET` requests for the path carrying all the query
+    /// parameters, compared after decoding them.
+    ///
+    /// # Arguments
+    /// * `endpoint` - Path relative to the server URL, eg: `get-secret`.
+    /// * `query` - Decoded names and values of the query parameters to match, eg: `[("key", "my key")]`.
+    /// * `plaintext` - JSON value to encrypt.
+    pub fn serve_secret_for(&self, endpoint: &str, query: &[(&str, &str)], plaintext: &Value) {
+        let mut mock = Mock::given(method("GET")).and(path(format!("/{}", endpoint.trim_start_matches('/'))));
+        for (name, value) in query {
+            mock = mock.and(query_param(*name, *value));
+        }
+        let body = serde_json::json!({ "detail": self.encrypt(plaintext) });
+        self.block_on(mock.respond_with(ResponseTemplate::new(200).set_body_json(body)).mount(&self.server));
+    }
+
+    /// Raw query strings of the requests received so far, as sent by the client, in the order of arrival.
+    pub fn received_queries(&self) -> Vec<String> {
+        let requests = self.block_on(self.server.received_requests()).unwrap_or_default();
+        requests.iter().map(|request| request.url.query().unwrap_or_default().to_string()).collect()
+    }
+
+    /// Serves `429 Too Many Requests` with a `Retry-After` header to the first `GET` requests for the path,
+    /// taking precedence over the responses served afterwards.
+    ///
+    /// # Arguments
+    /// * `endpoint` - Path relative to the server URL, eg: `get-secret`.
+    /// * `times` - Number of requests to rate limit.
+    /// * `retry_after` - Value of the `Retry-After` header, eg: `1`.
+    pub fn serve_rate_limited(&self, endpoint: &str, times: u64, retry_after: &str) {
+        let response = ResponseTemplate::new(429)
+            .insert_header("Retry-After", retry_after)
+            .set_body_json(serde_json::json!({ "detail": "Too many requests" }));
+        let mock = Mock::given(method("GET"))
+            .and(path(format!("/{}", endpoint.trim_start_matches('/'))))
+            .respond_with(response)
+            .up_to_n_times(times);
+        self.block_on(mock.mount(&self.server));
+    }
+
+    /// Serves an arbitrary JSON body with the given status code to every `GET` request for the path.
+    ///
+    /// # Arguments
+    /// * `endpoint` - Path relative to the server URL, eg: `get-secret`.
+    /// * `status` - HTTP status code of the response.
+    /// * `body` - JSON body of the response.
+    pub fn serve_response(&self, endpoint: &str, status: u16, body: Value) {
+        let mock = Mock::given(method("GET"))
+            .and(path(format!("/{}", endpoint.trim_start_matches('/'))))
+            .respond_with(ResponseTemplate::new(status).set_body_json(body));
+        self.block_on(mock.mount(&self.server));
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        // Dropping a runtime blocks, which isn't allowed within an async test
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
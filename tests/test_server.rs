@@ -0,0 +1,332 @@
+#![cfg(feature = "test-server")]
+
+use serde_json::json;
+use std::io::Write;
+use vaultapi::client::VaultClient;
+use vaultapi::decipher::{self, PayloadCompression, TransitOptions};
+use vaultapi::error::{DecryptError, Error};
+use vaultapi::test_server::TestServer;
+
+/// Retrieves a secret of the `default` table from the test server.
+fn get_secret(server: &TestServer, key: &str) -> Result<serde_json::Value, Error> {
+    let mut config = server.config();
+    config.table_name = "default".to_string();
+    config.get_secret = key.to_string();
+    config.retries = 0;
+    VaultClient::new(config)?.server_connection()
+}
+
+#[test]
+fn string_detail_is_decrypted() {
+    let server = TestServer::start("secret");
+    server.serve_secret("get-secret", &json!({"password": "hunter2"}));
+    assert_eq!(get_secret(&server, "password").unwrap(), json!({"password": "hunter2"}));
+}
+
+#[test]
+fn object_detail_with_an_error_is_reported() {
+    let server = TestServer::start("secret");
+    server.serve_detail("get-secret", json!({"error": "table not found"}));
+    match get_secret(&server, "password") {
+        Err(Error::Response(message)) => assert!(message.contains("table not found"), "{}", message),
+        other => panic!("Expected a response error, received: {:?}", other),
+    }
+}
+
+#[test]
+fn null_detail_is_reported() {
+    let server = TestServer::start("secret");
+    server.serve_detail("get-secret", json!(null));
+    assert!(matches!(get_secret(&server, "password"), Err(Error::Response(_))));
+}
+
+#[test]
+fn array_detail_is_decrypted_element_by_element() {
+    let server = TestServer::start("secret");
+    let detail = json!([server.encrypt(&json!({"user": "admin"})), "not a ciphertext"]);
+    server.serve_detail("get-secret", detail);
+    let decrypted = get_secret(&server, "user").unwrap();
+    assert_eq!(decrypted[0], json!({"user": "admin"}));
+    assert_eq!(decrypted[1]["index"], json!(1));
+    assert!(decrypted[1]["error"].is_string());
+}
+
+#[test]
+fn wrong_apikey_fails_to_decrypt() {
+    let server = TestServer::start("secret");
+    server.serve_secret("get-secret", &json!({"password": "hunter2"}));
+    let mut config = server.config();
+    config.apikey = "another".to_string();
+    config.table_name = "default".to_string();
+    config.get_secret = "password".to_string();
+    let result = VaultClient::new(config).and_then(|vault| vault.server_connection());
+    assert!(matches!(result, Err(Error::Decrypt(_))));
+}
+
+#[test]
+fn previous_apikey_decrypts_during_a_rotation() {
+    // Secrets were encrypted with the old apikey, while the client already has the new one
+    let server = TestServer::start("old-apikey");
+    server.serve_secret("get-secret", &json!({"password": "hunter2"}));
+    let rotated = |previous: &str| {
+        let mut config = server.config();
+        config.apikey = "new-apikey".to_string();
+        config.apikey_previous = previous.to_string();
+        config.table_name = "default".to_string();
+        config.get_secret = "password".to_string();
+        VaultClient::new(config).and_then(|vault| vault.server_connection())
+    };
+    assert!(matches!(rotated(""), Err(Error::Decrypt(_))));
+    assert_eq!(rotated("old-apikey").unwrap(), json!({"password": "hunter2"}));
+}
+
+/// Retrieves a secret by table and key names, from a server only answering for exactly those names.
+fn get_named_secret(table: &str, key: &str, percent_encode: bool) -> (Result<serde_json::Value, Error>, Vec<String>) {
+    let server = TestServer::start("secret");
+    server.serve_secret_for("get-secret", &[("table_name", table), ("key", key)], &json!({key: "hunter2"}));
+    let mut config = server.config();
+    config.table_name = table.to_string();
+    config.get_secret = key.to_string();
+    config.retries = 0;
+    config.percent_encode = percent_encode;
+    let result = VaultClient::new(config).and_then(|vault| vault.server_connection());
+    (result, server.received_queries())
+}
+
+#[test]
+fn names_with_spaces_slashes_and_unicode_round_trip() {
+    let names = [("my table", "my key"), ("team/billing", "db/password#primary"), ("tablé", "clé 🔑")];
+    for percent_encode in [false, true] {
+        for (table, key) in names {
+            let (result, _) = get_named_secret(table, key, percent_encode);
+            assert_eq!(result.unwrap(), json!({key: "hunter2"}), "{} / {} (percent_encode: {})", table, key, percent_encode);
+        }
+    }
+}
+
+#[test]
+fn percent_encoding_leaves_only_unreserved_characters() {
+    let (result, queries) = get_named_secret("team/billing", "my key~1", true);
+    assert!(result.is_ok(), "{:?}", result);
+    assert_eq!(queries, vec!["table_name=team%2Fbilling&key=my%20key~1"]);
+    let (result, queries) = get_named_secret("team/billing", "my key~1", false);
+    assert!(result.is_ok(), "{:?}", result);
+    assert_eq!(queries, vec!["table_name=team%2Fbilling&key=my+key%7E1"]);
+}
+
+#[test]
+fn names_with_control_characters_are_rejected() {
+    for percent_encode in [false, true] {
+        let (result, queries) = get_named_secret("default", "pass\nword", percent_encode);
+        match result {
+            Err(Error::Config(message)) => assert!(message.contains("control characters"), "{}", message),
+            other => panic!("Expected a config error, received: {:?}", other),
+        }
+        assert!(queries.is_empty(), "{:?}", queries);
+    }
+}
+
+#[test]
+fn table_rows_are_iterated_from_a_single_ciphertext_or_one_per_row() {
+    let server = TestServer::start("secret");
+    server.serve_secret("get-table", &json!({"user": "admin", "password": "hunter2"}));
+    let mut config = server.config();
+    config.retries = 0;
+    let vault = VaultClient::new(config).unwrap();
+    let mut rows: Vec<(String, serde_json::Value)> = vault.get_table_iter("default").map(Result::unwrap).collect();
+    rows.sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(rows, vec![("password".to_string(), json!("hunter2")), ("user".to_string(), json!("admin"))]);
+
+    let server = TestServer::start("secret");
+    let detail = json!({"user": server.encrypt(&json!("admin")), "password": "not a ciphertext"});
+    server.serve_detail("get-table", detail);
+    let mut config = server.config();
+    config.retries = 0;
+    let vault = VaultClient::new(config).unwrap();
+    let rows: Vec<_> = vault.get_table_iter("default").collect();
+    assert_eq!(rows.len(), 2);
+    assert!(rows.iter().any(|row| matches!(row, Ok((key, value)) if key == "user" && value == &json!("admin"))));
+    assert!(rows.iter().any(|row| matches!(row, Err(Error::Decrypt(_)))));
+}
+
+/// Retrieves a secret whose plaintext the server gzipped before encrypting it.
+fn get_gzipped_secret(plaintext: &[u8], decompress: PayloadCompression) -> Result<serde_json::Value, Error> {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(plaintext).unwrap();
+    let compressed = encoder.finish().unwrap();
+    let ciphertext = decipher::transit_encrypt_bytes(&"secret".to_string(), &compressed, &TransitOptions::default(), None);
+    let server = TestServer::start("secret");
+    server.serve_detail("get-secret", json!(ciphertext.unwrap()));
+    let mut config = server.config();
+    config.table_name = "default".to_string();
+    config.get_secret = "password".to_string();
+    config.retries = 0;
+    config.transit_decompress = decompress;
+    VaultClient::new(config)?.server_connection()
+}
+
+#[test]
+fn gzipped_plaintext_is_decompressed_before_parsing() {
+    let plaintext = br#"{"password": "hunter2"}"#;
+    for decompress in [PayloadCompression::Gzip, PayloadCompression::Auto] {
+        assert_eq!(get_gzipped_secret(plaintext, decompress).unwrap(), json!({"password": "hunter2"}));
+    }
+    assert!(matches!(
+        get_gzipped_secret(plaintext, PayloadCompression::None),
+        Err(Error::Decrypt(DecryptError::JsonParse(_)))
+    ));
+}
+
+#[test]
+fn decompression_failures_are_told_apart_from_invalid_json() {
+    // Plain JSON is passed through by auto-detection, but rejected when gzip is expected
+    let server = TestServer::start("secret");
+    server.serve_secret("get-secret", &json!({"password": "hunter2"}));
+    let get = |decompress| {
+        let mut config = server.config();
+        config.table_name = "default".to_string();
+        config.get_secret = "password".to_string();
+        config.transit_decompress = decompress;
+        VaultClient::new(config).and_then(|vault| vault.server_connection())
+    };
+    assert_eq!(get(PayloadCompression::Auto).unwrap(), json!({"password": "hunter2"}));
+    match get(PayloadCompression::Gzip) {
+        Err(Error::Decrypt(err @ DecryptError::Decompress { compression: PayloadCompression::Gzip, .. })) => {
+            assert!(err.to_string().starts_with("Failed to decompress decrypted data as gzip"), "{}", err)
+        }
+        other => panic!("Expected a decompression error, received: {:?}", other),
+    }
+    assert!(matches!(
+        get_gzipped_secret(b"not json", PayloadCompression::Gzip),
+        Err(Error::Decrypt(DecryptError::JsonParse(_)))
+    ));
+}
+
+#[test]
+fn pagination_ends_when_the_server_ignores_the_page() {
+    let server = TestServer::start("secret");
+    server.serve_secret("get-table", &json!({"user": "admin", "password": "hunter2"}));
+    let mut config = server.config();
+    config.retries = 0;
+    config.page_size = 2;
+    let vault = VaultClient::new(config).unwrap();
+    let rows: Vec<_> = vault.get_table_iter("default").map(Result::unwrap).collect();
+    assert_eq!(rows.len(), 2);
+    assert_eq!(server.received_queries().len(), 2);
+    assert_eq!(vault.get_table("default").unwrap(), json!({"user": "admin", "password": "hunter2"}));
+    assert_eq!(server.received_queries().len(), 4);
+}
+
+#[test]
+fn pagination_ends_when_the_next_page_is_the_current_one() {
+    let server = TestServer::start("secret");
+    let detail = server.encrypt(&json!({"user": "admin"}));
+    server.serve_response("get-table", 200, json!({"detail": detail, "next": 1}));
+    let mut config = server.config();
+    config.retries = 0;
+    config.page_size = 1;
+    let vault = VaultClient::new(config).unwrap();
+    assert_eq!(vault.get_table_iter("default").count(), 1);
+    assert_eq!(vault.get_table("default").unwrap(), json!({"user": "admin"}));
+    assert_eq!(server.received_queries().len(), 2);
+}
+
+#[test]
+fn rate_limited_request_is_retried_after_the_delay() {
+    let server = TestServer::start("secret");
+    server.serve_rate_limited("get-secret", 1, "1");
+    server.serve_secret("get-secret", &json!({"password": "hunter2"}));
+    let mut config = server.config();
+    config.table_name = "default".to_string();
+    config.get_secret = "password".to_string();
+    config.retries = 1;
+    let started = std::time::Instant::now();
+    let vault = VaultClient::new(config).unwrap();
+    assert_eq!(vault.server_connection().unwrap(), json!({"password": "hunter2"}));
+    assert!(started.elapsed() >= std::time::Duration::from_secs(1), "{:?}", started.elapsed());
+    assert_eq!(server.received_queries().len(), 2);
+
+    // Without retries left, the rate limit is reported
+    let server = TestServer::start("secret");
+    server.serve_rate_limited("get-secret", 1, "1");
+    server.serve_secret("get-secret", &json!({"password": "hunter2"}));
+    match get_secret(&server, "password") {
+        Err(Error::Status { code: 429, .. }) => {}
+        other => panic!("Expected a 429 status error, received: {:?}", other),
+    }
+}
+
+#[test]
+fn get_secrets_reports_the_keys_that_fail_to_decrypt_alongside_the_others() {
+    let server = TestServer::start("secret");
+    let detail = json!({"user": server.encrypt(&json!("admin")), "password": "bm9wZQ=="});
+    server.serve_detail("get-secrets", detail);
+    let mut config = server.config();
+    config.table_name = "default".to_string();
+    config.get_secrets = "user,password".to_string();
+    config.retries = 0;
+    let secrets = VaultClient::new(config).and_then(|vault| vault.server_connection()).unwrap();
+    assert_eq!(secrets["user"], json!("admin"));
+    let error = secrets["password"]["error"].as_str().unwrap();
+    assert_eq!(error, "Ciphertext is too short");
+}
+
+#[test]
+fn forbidden_response_reports_the_server_error_message() {
+    let server = TestServer::start("secret");
+    server.serve_response("get-secret", 403, json!({"detail": {"error": "invalid key"}}));
+    match get_secret(&server, "password") {
+        Err(Error::Status { code, message }) => {
+            assert_eq!(code, 403);
+            assert!(message.starts_with("Server responded with 403 Forbidden: invalid key"), "{}", message);
+        }
+        other => panic!("Expected a 403 status error, received: {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn client_is_built_used_and_dropped_within_an_async_runtime() {
+    let server = TestServer::start("secret");
+    server.serve_secret("get-secret", &json!({"password": "hunter2"}));
+    let mut config = server.config();
+    config.retries = 0;
+    let vault = VaultClient::builder(config).build_async().await.unwrap();
+    assert_eq!(vault.get_secret_async("default", "password").await.unwrap(), json!({"password": "hunter2"}));
+    drop(vault);
+}
+
+#[test]
+fn parallel_retrieval_reports_each_key_on_its_own() {
+    let server = TestServer::start("secret");
+    for key in ["a", "b", "c"] {
+        server.serve_secret_for("get-secret", &[("key", key)], &json!({ key: key.to_uppercase() }));
+    }
+    let mut config = server.config();
+    config.table_name = "default".to_string();
+    config.get_secrets = "a, b,c,missing".to_string();
+    config.parallel_limit = 2;
+    config.retries = 0;
+    let results = VaultClient::new(config).and_then(|vault| vault.get_secrets_parallel()).unwrap();
+    assert_eq!(results.len(), 4);
+    for key in ["a", "b", "c"] {
+        assert_eq!(results[key].as_ref().unwrap(), &json!({ key: key.to_uppercase() }));
+    }
+    assert!(matches!(results["missing"], Err(Error::Status { code: 404, .. })), "{:?}", results["missing"]);
+}
+
+#[test]
+fn table_with_one_ciphertext_per_row_is_accepted_by_both_table_apis() {
+    let server = TestServer::start("secret");
+    let detail = json!({"user": server.encrypt(&json!("admin")), "password": server.encrypt(&json!("hunter2"))});
+    server.serve_detail("get-table", detail);
+    for page_size in [0, 10] {
+        let mut config = server.config();
+        config.retries = 0;
+        config.page_size = page_size;
+        let vault = VaultClient::new(config).unwrap();
+        let table = vault.get_table("default").unwrap();
+        assert_eq!(table, json!({"user": "admin", "password": "hunter2"}), "page size {}", page_size);
+        let rows: serde_json::Map<String, serde_json::Value> = vault.get_table_iter("default").map(Result::unwrap).collect();
+        assert_eq!(serde_json::Value::Object(rows), table, "page size {}", page_size);
+    }
+}